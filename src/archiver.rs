@@ -0,0 +1,991 @@
+use crate::attrs::Attrs;
+use crate::auxiliary::{DirGuard, clean_stale_partials};
+use crate::dict::{self, S_DICT};
+use crate::error::{Error, Operation, Result};
+use crate::events::{ConsoleReporter, Event, Fanout, Reporter, Verbosity};
+use crate::exec::{S_ARCHIVE, action_of, entry_archive, human_size, is_archive_name, is_tool_name};
+use crate::filter::{Filter, Members};
+use crate::journal::{self, ItemState, Journal, S_EXTRACT_JOURNAL, S_JOURNAL};
+use crate::list::{ArchiveEntry, ListStyle};
+use crate::manifest::ManifestFormat;
+use crate::params::{Strategy, ZstdParams};
+use crate::policy::{EntryDecision, EntryPolicy, HardlinkPolicy, SpecialPolicy, SymlinkPolicy};
+use crate::preflight;
+use crate::report::{OutputFormat, Report};
+use crate::safety::{ConflictPolicy, ExistingPolicy, TarbombPolicy};
+use crate::seekable;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::{self, File, create_dir_all, read_dir};
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Direction of a batch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Pack every item into `<name>.tar.zst`
+    #[default]
+    Compress,
+    /// Unpack every `*.tar.zst`
+    Extract,
+    /// Check every `*.tar.zst` can be read back, without changing anything
+    Verify,
+    /// Print the entries of every `*.tar.zst`, without changing anything
+    List,
+}
+
+/// Options of a batch, set up builder-style
+///
+/// ```no_run
+/// use zst_compress::archiver::{Archiver, BatchOptions, Mode};
+///
+/// let options = BatchOptions::new("/data/projects")
+///     .target("/archive/projects")
+///     .mode(Mode::Compress)
+///     .level(12)
+///     .exclude("*.tmp")
+///     .preserve(true);
+/// let summary = Archiver::new(options).run().unwrap();
+/// println!("{} of {} items failed", summary.failed(), summary.total());
+/// ```
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    pub(crate) source_dir: PathBuf,
+    pub(crate) target_dir: Option<PathBuf>,
+    pub(crate) mode: Mode,
+    pub(crate) zstd: ZstdParams,
+    pub(crate) level_tree: u8,
+    pub(crate) manifest: Option<ManifestFormat>,
+    pub(crate) embed: bool,
+    pub(crate) attrs: Attrs,
+    pub(crate) policy: EntryPolicy,
+    pub(crate) conflict: ConflictPolicy,
+    pub(crate) existing: ExistingPolicy,
+    pub(crate) tarbomb: TarbombPolicy,
+    pub(crate) metadata: Vec<(String, String)>,
+    pub(crate) list_style: ListStyle,
+    pub(crate) members: Vec<String>,
+    pub(crate) strip_components: usize,
+    pub(crate) items: Option<Vec<PathBuf>>,
+    pub(crate) include: Vec<String>,
+    pub(crate) exclude: Vec<String>,
+    #[cfg(feature = "regex")]
+    pub(crate) include_regex: Vec<String>,
+    #[cfg(feature = "regex")]
+    pub(crate) exclude_regex: Vec<String>,
+    pub(crate) preserve: bool,
+    pub(crate) flag: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) preflight: bool,
+    pub(crate) verify: bool,
+    pub(crate) depth: usize,
+    pub(crate) jobs: usize,
+    pub(crate) threads: Option<usize>,
+    pub(crate) report: Option<PathBuf>,
+    pub(crate) format: OutputFormat,
+    pub(crate) verbosity: Verbosity,
+    pub(crate) resume: bool,
+    pub(crate) dictionary: Option<PathBuf>,
+    pub(crate) train_dictionary: Option<usize>,
+    pub(crate) frame_size: Option<usize>,
+}
+
+impl BatchOptions {
+    /// Process the items found in `source_dir`, with the CLI defaults for everything else
+    pub fn new(source_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            source_dir: source_dir.into(),
+            target_dir: None,
+            mode: Mode::Compress,
+            zstd: ZstdParams::default(),
+            level_tree: 4,
+            manifest: None,
+            embed: false,
+            attrs: Attrs::default(),
+            policy: EntryPolicy::default(),
+            conflict: ConflictPolicy::default(),
+            existing: ExistingPolicy::default(),
+            tarbomb: TarbombPolicy::default(),
+            metadata: vec![],
+            list_style: ListStyle::Tree,
+            members: vec![],
+            strip_components: 0,
+            items: None,
+            include: vec![],
+            exclude: vec![],
+            #[cfg(feature = "regex")]
+            include_regex: vec![],
+            #[cfg(feature = "regex")]
+            exclude_regex: vec![],
+            preserve: false,
+            flag: false,
+            dry_run: false,
+            preflight: true,
+            verify: false,
+            depth: 1,
+            jobs: 1,
+            threads: None,
+            report: None,
+            format: OutputFormat::Human,
+            verbosity: Verbosity::Normal,
+            resume: false,
+            dictionary: None,
+            train_dictionary: None,
+            frame_size: None,
+        }
+    }
+
+    /// Options putting back the items of the compress run journaled in `dir`,
+    /// the output directory of that run
+    ///
+    /// Every item the journal has as archived is extracted from its archive
+    /// in `dir` into the directory it was read from, and the archive is
+    /// removed. Archives already gone are left out.
+    pub fn restore(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        let run = journal::load(&dir.join(S_JOURNAL))?;
+        if run.mode != Mode::Compress {
+            return Err(Error::InvalidOption(format!(
+                "the journal in {dir:?} is of a {:?} run, only compress runs can be restored",
+                run.mode
+            )));
+        }
+        let mut items: Vec<_> = run
+            .items
+            .into_iter()
+            .filter(|(_, state)| *state >= ItemState::Archived)
+            .filter_map(|(f_path, _)| {
+                let f_name = f_path.file_name()?.to_string_lossy();
+                Some(f_path.with_file_name(format!("{f_name}{S_ARCHIVE}")))
+            })
+            .filter(|archive| dir.join(archive).is_file())
+            .collect();
+        items.sort();
+        Ok(Self::new(dir)
+            .target(run.source)
+            .mode(Mode::Extract)
+            .items(items))
+    }
+
+    /// Output directory [default: the source directory]
+    pub fn target(mut self, target_dir: impl Into<PathBuf>) -> Self {
+        self.target_dir = Some(target_dir.into());
+        self
+    }
+
+    /// Compress, extract or verify
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Zstandard compress level [default: 5]
+    ///
+    /// Negative levels are the fast ones, levels above 19 need [`Self::ultra`].
+    pub fn level(mut self, level_zstd: i32) -> Self {
+        self.zstd.level = level_zstd;
+        self
+    }
+
+    /// Allow the levels above 19, up to 22
+    pub fn ultra(mut self, ultra: bool) -> Self {
+        self.zstd.ultra = ultra;
+        self
+    }
+
+    /// Long-distance matching with a window of `2^window_log` bytes, for large
+    /// items with repetitions far apart, e.g. VM images
+    pub fn long(mut self, window_log: u32) -> Self {
+        self.zstd.long = Some(window_log);
+        self
+    }
+
+    /// Window of `2^window_log` bytes, overriding the one of the level or of [`Self::long`]
+    pub fn window_log(mut self, window_log: u32) -> Self {
+        self.zstd.window_log = Some(window_log);
+        self
+    }
+
+    /// Match finder of the encoder, overriding the one of the level
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.zstd.strategy = Some(strategy);
+        self
+    }
+
+    /// Depth of the `*_archived-filelist.txt` listing [default: 4]
+    pub fn listing_depth(mut self, level_tree: u8) -> Self {
+        self.level_tree = level_tree;
+        self
+    }
+
+    /// Also write `*_archived-manifest.<ext>` beside each archive, with the
+    /// exact size, mtime, mode, symlink target and SHA-256 of every entry
+    ///
+    /// See [`manifest`](crate::manifest).
+    pub fn manifest(mut self, format: ManifestFormat) -> Self {
+        self.manifest = Some(format);
+        self
+    }
+
+    /// Carry the listing, the manifest and metadata inside each archive
+    ///
+    /// See [`Embedded`](crate::embed::Embedded).
+    pub fn embed(mut self, embed: bool) -> Self {
+        self.embed = embed;
+        self
+    }
+
+    /// Add `key` = `value` to the embedded metadata, replacing a standard key
+    /// of the same name; may be called repeatedly
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.push((key.into(), value.into()));
+        self
+    }
+
+    /// Record owner and group names beside the uid and gid of each entry,
+    /// which extraction maps back to ids by name
+    pub fn owner_names(mut self, owner_names: bool) -> Self {
+        self.attrs.owner_names = owner_names;
+        self
+    }
+
+    /// Record extended attributes on compression and restore them on
+    /// extraction, POSIX ACLs and SELinux labels included
+    pub fn xattrs(mut self, xattrs: bool) -> Self {
+        self.attrs.xattrs = xattrs;
+        self
+    }
+
+    /// Give extracted entries their recorded owner, or leave them to the
+    /// extracting user [default: only when running as root]
+    pub fn same_owner(mut self, same_owner: bool) -> Self {
+        self.attrs.same_owner = Some(same_owner);
+        self
+    }
+
+    /// Restore the recorded uid and gid without mapping owner names
+    pub fn numeric_owner(mut self, numeric_owner: bool) -> Self {
+        self.attrs.numeric_owner = numeric_owner;
+        self
+    }
+
+    /// Keep the setuid, setgid and sticky bits of extracted entries
+    pub fn same_permissions(mut self, same_permissions: bool) -> Self {
+        self.attrs.same_permissions = same_permissions;
+        self
+    }
+
+    /// Store symlinks as links or archive what they point to [default: store]
+    pub fn symlinks(mut self, symlinks: SymlinkPolicy) -> Self {
+        self.policy.symlinks = symlinks;
+        self
+    }
+
+    /// Store further names of a file as hard links or with its contents
+    /// again [default: keep]
+    pub fn hardlinks(mut self, hardlinks: HardlinkPolicy) -> Self {
+        self.policy.hardlinks = hardlinks;
+        self
+    }
+
+    /// Leave the holes of sparse files out of the archives [default: true]
+    pub fn sparse(mut self, sparse: bool) -> Self {
+        self.policy.sparse = sparse;
+        self
+    }
+
+    /// Skip FIFOs, sockets and device nodes, or store them [default: skip]
+    pub fn special(mut self, special: SpecialPolicy) -> Self {
+        self.policy.special = special;
+        self
+    }
+
+    /// What compression does with an archive already there; dry runs
+    /// report each one [default: fail]
+    pub fn conflict(mut self, conflict: ConflictPolicy) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    /// What extraction does with entries whose destination exists already
+    /// [default: overwrite]
+    pub fn existing(mut self, existing: ExistingPolicy) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Unpack archives whose entries are not all below `<name>/` into
+    /// `<name>/`, or refuse them [default: redirect]
+    pub fn tarbomb(mut self, tarbomb: TarbombPolicy) -> Self {
+        self.tarbomb = tarbomb;
+        self
+    }
+
+    /// How [`Mode::List`] prints the entries of each archive [default: tree]
+    pub fn list_style(mut self, list_style: ListStyle) -> Self {
+        self.list_style = list_style;
+        self
+    }
+
+    /// Only process items whose name matches the glob pattern, or any of
+    /// the other include patterns; may be called repeatedly
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.include.push(pattern.into());
+        self
+    }
+
+    /// Skip items whose name matches the glob pattern; may be called repeatedly
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude.push(pattern.into());
+        self
+    }
+
+    /// Only process items whose name matches the regex, or any of the other
+    /// include patterns; may be called repeatedly
+    #[cfg(feature = "regex")]
+    pub fn include_regex(mut self, pattern: impl Into<String>) -> Self {
+        self.include_regex.push(pattern.into());
+        self
+    }
+
+    /// Skip items whose name matches the regex; may be called repeatedly
+    #[cfg(feature = "regex")]
+    pub fn exclude_regex(mut self, pattern: impl Into<String>) -> Self {
+        self.exclude_regex.push(pattern.into());
+        self
+    }
+
+    /// Only extract the entries whose path, or the path of a directory above,
+    /// matches the glob pattern or any of the other member patterns; may be
+    /// called repeatedly
+    ///
+    /// Archives are kept after a partial extraction. An archive without any
+    /// matching entry fails.
+    pub fn member(mut self, pattern: impl Into<String>) -> Self {
+        self.members.push(pattern.into());
+        self
+    }
+
+    /// Drop the first `n` components of entry paths on extraction, entries
+    /// with no more components are skipped
+    pub fn strip_components(mut self, n: usize) -> Self {
+        self.strip_components = n;
+        self
+    }
+
+    /// Process exactly these items, given relative to the source directory,
+    /// instead of collecting them
+    pub fn items(mut self, items: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+        let relative = |item: PathBuf| {
+            let item: PathBuf = item
+                .components()
+                .filter(|c| *c != Component::CurDir)
+                .collect();
+            Path::new(".").join(item)
+        };
+        self.items = Some(
+            items
+                .into_iter()
+                .map(|item| relative(item.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Keep the originals after processing
+    pub fn preserve(mut self, preserve: bool) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Leave a `*_archived-message.txt` beside each compressed original
+    pub fn flag(mut self, flag: bool) -> Self {
+        self.flag = flag;
+        self
+    }
+
+    /// Report what would be done without touching any file
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Check free space and write permissions before compressing or
+    /// extracting anything, see [`preflight`](crate::preflight); dry runs
+    /// print what is missing [default: true]
+    pub fn preflight(mut self, preflight: bool) -> Self {
+        self.preflight = preflight;
+        self
+    }
+
+    /// Read back each new archive and compare it against its source before
+    /// the original is removed
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Level below the source directory whose entries become archives
+    /// [default: 1, the immediate children]
+    ///
+    /// Extract and verify modes look for `*.tar.zst` at any depth when this is
+    /// above 1. Results are placed under the target directory at the same
+    /// relative path as their item.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+
+    /// Number of items processed at the same time [default: 1]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Total number of threads, split between the parallel items and their
+    /// zstd workers [default: available CPUs]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads.max(1));
+        self
+    }
+
+    /// Write a JSON Lines record per item and a summary to `path`
+    pub fn report(mut self, path: impl Into<PathBuf>) -> Self {
+        self.report = Some(path.into());
+        self
+    }
+
+    /// Print progress for people or JSON records on stdout
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// How much the console prints [default: one line per item]
+    pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+        self.verbosity = verbosity;
+        self
+    }
+
+    /// Pick up the run recorded in the journal of the target directory,
+    /// finishing the items it left half done
+    ///
+    /// Every compress or extract run keeps such a journal, see [`Journal`]
+    /// for how long.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// Compress every item with the zstd dictionary in `path`
+    ///
+    /// A copy is kept beside the archives as `zst_dict-<ID>.dict`, where
+    /// extraction finds it by the ID in the zstd frame header.
+    pub fn dictionary(mut self, path: impl Into<PathBuf>) -> Self {
+        self.dictionary = Some(path.into());
+        self
+    }
+
+    /// Train a zstd dictionary of at most `max_size` bytes from samples of
+    /// the items and compress every item with it, see [`Self::dictionary`]
+    pub fn train_dictionary(mut self, max_size: usize) -> Self {
+        self.train_dictionary = Some(max_size);
+        self
+    }
+
+    /// Write each archive as independent zstd frames of `frame_size`
+    /// uncompressed bytes, followed by an index of its entries
+    ///
+    /// The index lets [`extract_entry`] seek straight to one entry, and
+    /// extraction decode the frames in parallel. See [`Index`].
+    ///
+    /// [`extract_entry`]: crate::seekable::extract_entry
+    /// [`Index`]: crate::seekable::Index
+    pub fn seekable(mut self, frame_size: usize) -> Self {
+        self.frame_size = Some(frame_size);
+        self
+    }
+
+    /// Write each archive as a single zstd frame, the default, undoing
+    /// [`Self::seekable`]
+    pub fn single_frame(mut self) -> Self {
+        self.frame_size = None;
+        self
+    }
+
+    /// Number of zstd workers each item gets out of the thread budget, shared
+    /// among the parallel jobs
+    pub fn zstd_workers(&self) -> u32 {
+        let threads = self
+            .threads
+            .or_else(|| thread::available_parallelism().ok().map(|n| n.get()))
+            .unwrap_or(1);
+        (threads / self.jobs).max(1) as u32
+    }
+
+    /// Directory the items are read from
+    pub fn source_dir(&self) -> &Path {
+        &self.source_dir
+    }
+
+    /// Directory the results are written to
+    pub fn target_dir(&self) -> &Path {
+        self.target_dir.as_deref().unwrap_or(&self.source_dir)
+    }
+
+    /// Directory the result of `f_path` goes to, mirroring its place below the source
+    pub(crate) fn item_target_dir(&self, f_path: &Path) -> PathBuf {
+        let relative = f_path
+            .parent()
+            .and_then(|parent| parent.strip_prefix(&self.source_dir).ok())
+            .unwrap_or(Path::new(""));
+        self.target_dir().join(relative)
+    }
+}
+
+/// What was done with one item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Compress,
+    Extract,
+    Verify,
+    List,
+    Skip,
+}
+
+/// Outcome of one processed item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ItemOutcome {
+    pub action: Action,
+    /// The item as found in the source directory
+    pub source: PathBuf,
+    /// The archive or extracted path, `None` for skipped items
+    pub destination: Option<PathBuf>,
+    /// Size of the file contents packed or unpacked
+    pub original_bytes: Option<u64>,
+    /// Size of the `.tar.zst`
+    pub archive_bytes: Option<u64>,
+    /// Number of tar entries read back, for verified and listed archives
+    pub entries: Option<usize>,
+    /// Entries of a listed archive
+    pub listing: Option<Vec<ArchiveEntry>>,
+    /// Symlinks, hard links, sparse and special files of a compressed item,
+    /// and what was done with them
+    pub decisions: Vec<EntryDecision>,
+    /// Time spent on the item
+    pub duration: Duration,
+}
+
+/// Outcomes of a whole batch
+#[derive(Debug, Default)]
+pub struct BatchSummary {
+    pub outcomes: Vec<ItemOutcome>,
+    pub failures: Vec<(PathBuf, Error)>,
+}
+
+impl BatchSummary {
+    /// Number of items looked at
+    pub fn total(&self) -> usize {
+        self.outcomes.len() + self.failures.len()
+    }
+
+    /// Number of items that failed
+    pub fn failed(&self) -> usize {
+        self.failures.len()
+    }
+}
+
+/// Batch engine driven by [`BatchOptions`]
+#[derive(Clone)]
+pub struct Archiver {
+    options: BatchOptions,
+    reporter: Option<Arc<dyn Reporter>>,
+}
+
+impl fmt::Debug for Archiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archiver")
+            .field("options", &self.options)
+            .field("reporter", &self.reporter.is_some())
+            .finish()
+    }
+}
+
+impl Archiver {
+    pub fn new(options: BatchOptions) -> Self {
+        Self {
+            options,
+            reporter: None,
+        }
+    }
+
+    /// Send the events of the run to `reporter` instead of printing them
+    ///
+    /// The JSON records of [`BatchOptions::format`] and
+    /// [`BatchOptions::report`] are still written.
+    pub fn reporter(mut self, reporter: Arc<dyn Reporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    pub fn options(&self) -> &BatchOptions {
+        &self.options
+    }
+
+    /// Items at the configured depth that pass the include/exclude filters
+    ///
+    /// Fails without looking at any item if a pattern does not compile.
+    pub fn collect_items(&self) -> Result<Vec<PathBuf>> {
+        let filter = Filter::new(&self.options)?;
+        let mut valid_entries = vec![];
+        // Archives can sit at any depth, whatever depth they were made at
+        let recursive = self.options.mode != Mode::Compress && self.options.depth > 1;
+        let source_dir = self.options.source_dir();
+        self.collect_level(source_dir, 1, recursive, &filter, &mut valid_entries)?;
+        Ok(valid_entries)
+    }
+
+    fn collect_level(
+        &self,
+        dir: &Path,
+        level: usize,
+        recursive: bool,
+        filter: &Filter,
+        valid_entries: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let entries = read_dir(dir).map_err(|e| Error::io(Operation::ReadDir, dir, e))?;
+        for entry_result in entries {
+            let entry = entry_result.map_err(|e| Error::io(Operation::ReadDir, dir, e))?;
+            let file_path = entry.path();
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            if file_name == S_JOURNAL
+                || file_name == S_EXTRACT_JOURNAL
+                || file_name.starts_with(S_DICT)
+            {
+                continue;
+            }
+            // Symlinked directories are not descended into
+            let descend = entry.file_type().is_ok_and(|t| t.is_dir())
+                && !is_tool_name(&file_name)
+                && (recursive || level < self.options.depth);
+
+            if descend {
+                self.collect_level(&file_path, level + 1, recursive, filter, valid_entries)?;
+            } else if (level == self.options.depth || recursive && is_archive_name(&file_name))
+                && filter.matches(&file_path)
+            {
+                valid_entries.push(file_path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compress or extract one item, `current` and `total` are only used for progress output
+    pub fn process_item(&self, f_path: &Path, current: usize, total: usize) -> Result<ItemOutcome> {
+        let console = self.console(true);
+        let events: &dyn Reporter = match &self.reporter {
+            Some(reporter) => reporter.as_ref(),
+            None => &console,
+        };
+        entry_archive(f_path, &self.options, current, total, events, None)
+    }
+
+    /// Process every item of the source directory
+    ///
+    /// Item failures are collected in the summary, only errors that stop the
+    /// whole batch are returned as `Err`.
+    ///
+    /// While it runs, the working directory of the whole process is the source
+    /// directory, restored when the run returns. A relative target is taken
+    /// from there, and nothing else in the process, another run included, may
+    /// rely on the working directory meanwhile.
+    pub fn run(&self) -> Result<BatchSummary> {
+        let started = Instant::now();
+        match self.options.mode {
+            Mode::Compress => {
+                self.options.zstd.validate()?;
+                seekable::check_frame_size(self.options.frame_size)?;
+            }
+            Mode::Extract => drop(Members::new(&self.options)?),
+            Mode::Verify | Mode::List => {}
+        }
+        // Opened before entering the source directory, relative paths stay as given
+        let report = self.open_report()?;
+        let dictionary = match &self.options.dictionary {
+            Some(path) => {
+                Some(fs::read(path).map_err(|e| Error::io(Operation::Dictionary, path, e))?)
+            }
+            None => None,
+        };
+        // A single archive is a batch of its own, run from the directory holding it
+        let source = self.options.source_dir();
+        let single = match self.options.mode != Mode::Compress && source.is_file() {
+            true => source.file_name().map(|name| Path::new(".").join(name)),
+            false => None,
+        };
+        let _guard = match (&single, source.parent()) {
+            (Some(_), Some(parent)) if !parent.as_os_str().is_empty() => DirGuard::new(parent)?,
+            (Some(_), _) => DirGuard::new(Path::new("."))?,
+            (None, _) => DirGuard::new(source)?,
+        };
+        // Paths below are relative to the source directory we just entered
+        let mut archiver = Archiver::new(BatchOptions {
+            source_dir: PathBuf::from("."),
+            target_dir: Some(
+                self.options
+                    .target_dir
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from(".")),
+            ),
+            ..self.options.clone()
+        });
+
+        let mut valid_entries = match (single, &self.options.items) {
+            (Some(f_path), _) => vec![f_path],
+            (None, Some(items)) => items.clone(),
+            (None, None) => archiver.collect_items()?,
+        };
+        // Only the jobs started share the thread budget
+        let jobs = self.options.jobs.clamp(1, valid_entries.len().max(1));
+        archiver.options.jobs = jobs;
+        let console = self.console(jobs == 1);
+        let events = Fanout(vec![
+            match &self.reporter {
+                Some(reporter) => reporter.as_ref(),
+                None => &console,
+            },
+            &report,
+        ]);
+
+        // Temporary files of an interrupted run never became valid output,
+        // nor do they count against the free space
+        let stale = archiver.clean_stale_partials(&valid_entries, &events);
+        valid_entries.retain(|f_path| !stale.contains(f_path));
+
+        // Nothing is changed before the whole run is known to fit
+        if self.options.preflight && matches!(self.options.mode, Mode::Compress | Mode::Extract) {
+            let findings = preflight::check(&valid_entries, &archiver.options);
+            for f_path in &findings.not_sized {
+                events.event(&Event::Info(&format!(
+                    "Unpacked size of {f_path:?} unknown without decoding it, left out of the space check"
+                )));
+            }
+            if self.options.dry_run {
+                for problem in &findings.problems {
+                    events.event(&Event::Error(&problem.to_string()));
+                }
+            } else if !findings.problems.is_empty() {
+                return Err(Error::Preflight(findings.problems));
+            }
+        }
+
+        let journal = archiver.open_journal(&valid_entries, &events)?;
+        archiver.prepare_dictionary(dictionary, &valid_entries, &events)?;
+
+        let total_items = valid_entries.len();
+        if total_items < 1 {
+            events.event(&Event::Info(&format!(
+                "No item in {:?} to process.",
+                self.options.source_dir()
+            )));
+        }
+        events.event(&Event::BatchStart {
+            total: total_items,
+            jobs,
+            zstd_workers: archiver.options.zstd_workers(),
+        });
+
+        // Workers take the next item from a shared index until all are done
+        let next_item = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(total_items));
+        let work = || {
+            loop {
+                let index = next_item.fetch_add(1, Ordering::Relaxed);
+                let Some(entry_path) = valid_entries.get(index) else {
+                    break;
+                };
+                let result = entry_archive(
+                    entry_path,
+                    &archiver.options,
+                    index + 1,
+                    total_items,
+                    &events,
+                    journal.as_ref(),
+                );
+                results.lock().unwrap().push((index, result));
+            }
+        };
+        if jobs == 1 {
+            work();
+        } else {
+            thread::scope(|scope| {
+                for _ in 0..jobs {
+                    scope.spawn(work);
+                }
+            });
+        }
+
+        // Keep the outcomes in the order of the items
+        let mut summary = BatchSummary::default();
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(index, _)| *index);
+        for (index, result) in results {
+            match result {
+                Ok(outcome) => summary.outcomes.push(outcome),
+                Err(e) => summary.failures.push((valid_entries[index].clone(), e)),
+            }
+        }
+        if let Some(journal) = journal
+            && self.options.mode == Mode::Extract
+            && summary.failures.is_empty()
+        {
+            journal.remove()?;
+        }
+        events.event(&Event::BatchDone {
+            summary: &summary,
+            duration: started.elapsed(),
+        });
+        Ok(summary)
+    }
+
+    /// Console output of the run, errors only when stdout carries JSON
+    fn console(&self, inline: bool) -> ConsoleReporter {
+        let verbosity = match self.options.format {
+            OutputFormat::Human => self.options.verbosity,
+            OutputFormat::Json => Verbosity::Quiet,
+        };
+        ConsoleReporter::new(verbosity, inline).list_style(self.options.list_style)
+    }
+
+    /// Report sinks of the run, JSON on stdout and/or in the report file
+    fn open_report(&self) -> Result<Report> {
+        let mut report = Report::new();
+        if self.options.format == OutputFormat::Json {
+            report.add_sink(Box::new(io::stdout()));
+        }
+        if let Some(path) = &self.options.report {
+            let file =
+                File::create(path).map_err(|e| Error::io(Operation::WriteReport, path, e))?;
+            report.add_sink(Box::new(file));
+        }
+        Ok(report)
+    }
+
+    /// Journal of the run in the target directory, none when only verifying
+    fn open_journal(&self, items: &[PathBuf], events: &dyn Reporter) -> Result<Option<Journal>> {
+        let options = &self.options;
+        let read_only = matches!(options.mode, Mode::Verify | Mode::List);
+        if read_only || options.dry_run && !options.resume {
+            return Ok(None);
+        }
+        let target_dir = options.target_dir();
+        if !options.dry_run {
+            create_dir_all(target_dir)
+                .map_err(|e| Error::io(Operation::WriteJournal, target_dir, e))?;
+        }
+        // The source directory was entered, it is the current one
+        let source = std::env::current_dir()
+            .map_err(|e| Error::io(Operation::ReadDir, options.source_dir(), e))?;
+        let journal = match options.resume {
+            true => Journal::resume(target_dir, options.mode, &source, options.dry_run)?,
+            false => Journal::create(target_dir, options.mode, &source)?,
+        };
+
+        if options.resume {
+            let resumed = items
+                .iter()
+                .filter(|f_path| journal.previous(f_path).is_some())
+                .count();
+            events.event(&Event::Info(&format!(
+                "Resume from {:?}: {resumed} of {} items were started before",
+                journal.path(),
+                items.len()
+            )));
+        }
+        Ok(Some(journal))
+    }
+
+    /// Save the given dictionary or train one, and compress with its sidecar
+    fn prepare_dictionary(
+        &mut self,
+        dictionary: Option<Vec<u8>>,
+        items: &[PathBuf],
+        events: &dyn Reporter,
+    ) -> Result<()> {
+        self.options.dictionary = None;
+        if self.options.mode != Mode::Compress || self.options.dry_run {
+            return Ok(());
+        }
+        let dictionary = match (dictionary, self.options.train_dictionary) {
+            (Some(dictionary), _) => dictionary,
+            (None, Some(max_size)) => {
+                let items: Vec<_> = items
+                    .iter()
+                    .filter(|f_path| action_of(f_path, Mode::Compress) == Action::Compress)
+                    .cloned()
+                    .collect();
+                match dict::train(&items, max_size) {
+                    Ok(dictionary) => dictionary,
+                    // Too few or too small samples, the batch still works without
+                    Err(e) => {
+                        events.event(&Event::Error(&format!(
+                            "Failed to train a dictionary, compressing without one: {e}"
+                        )));
+                        return Ok(());
+                    }
+                }
+            }
+            (None, None) => return Ok(()),
+        };
+
+        let target_dir = self.options.target_dir();
+        let path = dict::save(&dictionary, target_dir)
+            .map_err(|e| Error::io(Operation::Dictionary, target_dir, e))?;
+        events.event(&Event::Info(&format!(
+            "Compress with dictionary {:?}, {}",
+            path,
+            human_size(dictionary.len() as u64)
+        )));
+        self.options.dictionary = Some(path);
+        Ok(())
+    }
+
+    /// Remove temporary files in the directories the items come from and go to
+    fn clean_stale_partials(&self, items: &[PathBuf], events: &dyn Reporter) -> BTreeSet<PathBuf> {
+        let mut removed = BTreeSet::new();
+        let mut dirs = BTreeSet::new();
+        dirs.insert(self.options.source_dir().to_path_buf());
+        dirs.insert(self.options.target_dir().to_path_buf());
+        for f_path in items {
+            dirs.insert(f_path.parent().unwrap_or(Path::new(".")).to_path_buf());
+            dirs.insert(self.options.item_target_dir(f_path));
+        }
+        for dir in &dirs {
+            match clean_stale_partials(dir, self.options.dry_run) {
+                Ok(stale) => {
+                    for path in stale {
+                        events.event(&Event::Info(&format!(
+                            "Remove stale temporary file: {:?}",
+                            path
+                        )));
+                        removed.insert(path);
+                    }
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => events.event(&Event::Error(&format!(
+                    "Failed to clean temporary files in {:?}: {e}",
+                    dir
+                ))),
+            }
+        }
+        removed
+    }
+}
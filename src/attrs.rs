@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Which attributes are captured on compression and restored on extraction
+///
+/// Mode, uid, gid and mtime are always in the tar headers. Owner names and
+/// extended attributes go in a PAX extended header ahead of the entry, as
+/// `uname`, `gname` and `SCHILY.xattr.<name>` records like GNU tar writes
+/// them. POSIX ACLs and SELinux labels are the extended attributes
+/// `system.posix_acl_access`, `system.posix_acl_default` and
+/// `security.selinux`, so they travel the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Attrs {
+    /// Record owner and group names besides the uid and gid
+    pub owner_names: bool,
+    /// Record and restore extended attributes, ACLs and SELinux labels included
+    pub xattrs: bool,
+    /// Give extracted entries their recorded owner; `None` does it when running as root
+    pub same_owner: Option<bool>,
+    /// Restore the recorded uid and gid even when their names exist here
+    pub numeric_owner: bool,
+    /// Keep the setuid, setgid and sticky bits on extraction
+    pub same_permissions: bool,
+}
+
+impl Attrs {
+    /// Whether extraction gives entries their recorded owner
+    pub fn restores_owner(&self) -> bool {
+        self.same_owner.unwrap_or_else(is_root)
+    }
+
+    /// Whether entries get a PAX extended header on compression
+    pub(crate) fn captures(&self) -> bool {
+        self.owner_names || self.xattrs
+    }
+
+    /// Set up `archive` to restore what the tar crate restores itself
+    pub(crate) fn apply<R: Read>(&self, archive: &mut tar::Archive<R>) {
+        archive.set_unpack_xattrs(self.xattrs);
+        archive.set_preserve_permissions(self.same_permissions);
+    }
+}
+
+/// Names of uids and gids, looked up once each
+#[derive(Default)]
+pub(crate) struct Names {
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+/// Append a PAX extended header with the attributes `attrs` captures of
+/// `path`, for the entry appended next
+pub(crate) fn append_pax<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &Path,
+    metadata: &Metadata,
+    attrs: &Attrs,
+    names: &mut Names,
+) -> io::Result<()> {
+    let mut records = vec![];
+    if attrs.owner_names {
+        if let Some(user) = names.user(metadata) {
+            pax_record(&mut records, b"uname", user.as_bytes());
+        }
+        if let Some(group) = names.group(metadata) {
+            pax_record(&mut records, b"gname", group.as_bytes());
+        }
+    }
+    if attrs.xattrs {
+        // A symlink stored as a link keeps its own, a followed one those of its target
+        for (name, value) in xattrs(path, !metadata.is_symlink())? {
+            let key = [&b"SCHILY.xattr."[..], &name].concat();
+            pax_record(&mut records, &key, &value);
+        }
+    }
+    append_records(builder, &records)
+}
+
+/// Append again the PAX records of `entry`, read from another archive, for
+/// the entry appended next
+///
+/// The path, link name, size and sparse map are left out, the builder
+/// writes those of the entry itself.
+pub(crate) fn copy_pax<R: Read, W: Write>(
+    builder: &mut tar::Builder<W>,
+    entry: &mut tar::Entry<R>,
+) -> io::Result<()> {
+    let mut records = vec![];
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            let key = extension.key_bytes();
+            if !matches!(key, b"path" | b"linkpath" | b"size") && !key.starts_with(b"GNU.sparse.") {
+                pax_record(&mut records, key, extension.value_bytes());
+            }
+        }
+    }
+    append_records(builder, &records)
+}
+
+/// Append a PAX extended header holding `records`, if there are any
+fn append_records<W: Write>(builder: &mut tar::Builder<W>, records: &[u8]) -> io::Result<()> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let mut header = tar::Header::new_ustar();
+    header.set_path("././@PaxHeader")?;
+    header.set_entry_type(tar::EntryType::XHeader);
+    header.set_mode(0o644);
+    header.set_size(records.len() as u64);
+    header.set_cksum();
+    builder.append(&header, records)
+}
+
+/// One `<length> <key>=<value>\n` record, the length counting itself
+fn pax_record(records: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    records.extend_from_slice(len.to_string().as_bytes());
+    records.push(b' ');
+    records.extend_from_slice(key);
+    records.push(b'=');
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Uid and gid to give an entry about to be unpacked, `None` to leave the owner as is
+pub(crate) fn owner_of<R: Read>(
+    entry: &mut tar::Entry<R>,
+    attrs: &Attrs,
+) -> io::Result<Option<(u32, u32)>> {
+    if !attrs.restores_owner() {
+        return Ok(None);
+    }
+    let header = entry.header();
+    let (mut uid, mut gid) = (header.uid()? as u32, header.gid()? as u32);
+    if attrs.numeric_owner {
+        return Ok(Some((uid, gid)));
+    }
+    let mut user = header.username().ok().flatten().map(str::to_string);
+    let mut group = header.groupname().ok().flatten().map(str::to_string);
+    if let Some(extensions) = entry.pax_extensions()? {
+        for extension in extensions {
+            let extension = extension?;
+            match extension.key() {
+                Ok("uname") => user = extension.value().ok().map(str::to_string),
+                Ok("gname") => group = extension.value().ok().map(str::to_string),
+                _ => {}
+            }
+        }
+    }
+    // Names missing here fall back to the recorded ids, like GNU tar
+    if let Some(id) = user.as_deref().and_then(sys::user_id) {
+        uid = id;
+    }
+    if let Some(id) = group.as_deref().and_then(sys::group_id) {
+        gid = id;
+    }
+    Ok(Some((uid, gid)))
+}
+
+/// Give the unpacked `path` the owner `uid` and `gid`
+///
+/// Changing the owner clears the setuid and setgid bits, so with
+/// `same_permissions` the recorded `mode` is put back afterwards.
+pub(crate) fn set_owner(
+    path: &Path,
+    (uid, gid): (u32, u32),
+    mode: u32,
+    attrs: &Attrs,
+) -> io::Result<()> {
+    sys::lchown(path, uid, gid)?;
+    if attrs.same_permissions && mode & 0o6000 != 0 && !path.is_symlink() {
+        sys::set_mode(path, mode)?;
+    }
+    Ok(())
+}
+
+impl Names {
+    fn user(&mut self, metadata: &Metadata) -> Option<&str> {
+        let uid = sys::uid(metadata)?;
+        self.users
+            .entry(uid)
+            .or_insert_with(|| sys::user_name(uid))
+            .as_deref()
+    }
+
+    fn group(&mut self, metadata: &Metadata) -> Option<&str> {
+        let gid = sys::gid(metadata)?;
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| sys::group_name(gid))
+            .as_deref()
+    }
+}
+
+/// Extended attributes of `path`, or of what it leads to when `follow`
+fn xattrs(path: &Path, follow: bool) -> io::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        let names = match follow {
+            true => xattr::list_deref(path),
+            false => xattr::list(path),
+        };
+        let names = match names {
+            Ok(names) => names,
+            // Nothing to record on file systems without them
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut attrs = vec![];
+        for name in names {
+            let value = match follow {
+                true => xattr::get_deref(path, &name)?,
+                false => xattr::get(path, &name)?,
+            };
+            if let Some(value) = value {
+                attrs.push((name.as_bytes().to_vec(), value));
+            }
+        }
+        Ok(attrs)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (path, follow);
+        Ok(vec![])
+    }
+}
+
+fn is_root() -> bool {
+    #[cfg(unix)]
+    // SAFETY: geteuid has no preconditions and cannot fail
+    return unsafe { libc::geteuid() } == 0;
+    #[cfg(not(unix))]
+    false
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::{CStr, CString};
+    use std::fs::{self, Metadata};
+    use std::io;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    use std::path::Path;
+
+    pub fn uid(metadata: &Metadata) -> Option<u32> {
+        Some(metadata.uid())
+    }
+
+    pub fn gid(metadata: &Metadata) -> Option<u32> {
+        Some(metadata.gid())
+    }
+
+    pub fn lchown(path: &Path, uid: u32, gid: u32) -> io::Result<()> {
+        std::os::unix::fs::lchown(path, Some(uid), Some(gid))
+    }
+
+    pub fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))
+    }
+
+    /// Call a `get*_r` function with a growing buffer until it fits
+    fn lookup<T, U>(
+        call: impl Fn(&mut T, &mut [libc::c_char], &mut *mut T) -> libc::c_int,
+        read: impl FnOnce(&T) -> Option<U>,
+    ) -> Option<U> {
+        let mut buf = vec![0; 1024];
+        loop {
+            // SAFETY: the passwd and group structs are plain C data, all zeros is valid
+            let mut record: T = unsafe { std::mem::zeroed() };
+            let mut result = std::ptr::null_mut();
+            match call(&mut record, &mut buf, &mut result) {
+                0 if result.is_null() => return None,
+                0 => return read(&record),
+                libc::ERANGE if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                _ => return None,
+            }
+        }
+    }
+
+    pub fn user_name(uid: u32) -> Option<String> {
+        lookup(
+            // SAFETY: all pointers come from live buffers of the given length
+            |pwd, buf, result| unsafe {
+                libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result)
+            },
+            |pwd: &libc::passwd| name(pwd.pw_name),
+        )
+    }
+
+    pub fn group_name(gid: u32) -> Option<String> {
+        lookup(
+            // SAFETY: as in user_name
+            |grp, buf, result| unsafe {
+                libc::getgrgid_r(gid, grp, buf.as_mut_ptr(), buf.len(), result)
+            },
+            |grp: &libc::group| name(grp.gr_name),
+        )
+    }
+
+    pub fn user_id(user: &str) -> Option<u32> {
+        let user = CString::new(user).ok()?;
+        lookup(
+            // SAFETY: as in user_name
+            |pwd, buf, result| unsafe {
+                libc::getpwnam_r(user.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result)
+            },
+            |pwd: &libc::passwd| Some(pwd.pw_uid),
+        )
+    }
+
+    pub fn group_id(group: &str) -> Option<u32> {
+        let group = CString::new(group).ok()?;
+        lookup(
+            // SAFETY: as in user_name
+            |grp, buf, result| unsafe {
+                libc::getgrnam_r(group.as_ptr(), grp, buf.as_mut_ptr(), buf.len(), result)
+            },
+            |grp: &libc::group| Some(grp.gr_gid),
+        )
+    }
+
+    fn name(ptr: *const libc::c_char) -> Option<String> {
+        if ptr.is_null() {
+            return None;
+        }
+        // SAFETY: a non-null name of a record filled in by libc is a C string
+        let name = unsafe { CStr::from_ptr(ptr) };
+        name.to_str().ok().map(str::to_string)
+    }
+}
+
+/// Owners do not exist off Unix, entries keep the ones they get
+#[cfg(not(unix))]
+mod sys {
+    use std::fs::Metadata;
+    use std::io;
+    use std::path::Path;
+
+    pub fn uid(_: &Metadata) -> Option<u32> {
+        None
+    }
+
+    pub fn gid(_: &Metadata) -> Option<u32> {
+        None
+    }
+
+    pub fn lchown(_: &Path, _: u32, _: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn set_mode(_: &Path, _: u32) -> io::Result<()> {
+        Ok(())
+    }
+
+    pub fn user_name(_: u32) -> Option<String> {
+        None
+    }
+
+    pub fn group_name(_: u32) -> Option<String> {
+        None
+    }
+
+    pub fn user_id(_: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_: &str) -> Option<u32> {
+        None
+    }
+}
@@ -1,15 +1,18 @@
+use crate::error::{Error, Operation, Result};
+use std::fs::{File, read_dir, remove_file, rename};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
 
-static RET_DIR_ERROR: u8 = 3;
-
 pub struct DirGuard {
     original: PathBuf,
 }
 
 impl DirGuard {
-    pub fn new(target: &Path) -> Result<Self, u8> {
-        let original = std::env::current_dir().map_err(|_| RET_DIR_ERROR)?;
-        std::env::set_current_dir(target).map_err(|_| RET_DIR_ERROR)?;
+    pub fn new(target: &Path) -> Result<Self> {
+        let original = std::env::current_dir()
+            .map_err(|e| Error::io(Operation::ChangeDir, Path::new("."), e))?;
+        std::env::set_current_dir(target)
+            .map_err(|e| Error::io(Operation::ChangeDir, target, e))?;
         Ok(Self { original })
     }
 }
@@ -19,3 +22,151 @@ impl Drop for DirGuard {
         let _ = std::env::set_current_dir(&self.original);
     }
 }
+
+/// Prefix of temporary files, `zst_` keeps them out of the batch items
+pub static S_PARTIAL: &str = "zst_partial.";
+
+/// A file written under a temporary name and renamed into place on [`commit`]
+///
+/// The temporary file lives in the same directory as the final one, so the
+/// rename is atomic. Dropping without commit removes the temporary file; a
+/// crash leaves it behind for [`clean_stale_partials`].
+///
+/// [`commit`]: AtomicFile::commit
+pub struct AtomicFile {
+    file: Option<File>,
+    temp: PathBuf,
+    target: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(target: &Path) -> io::Result<Self> {
+        let f_name = target
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+        let temp = target.with_file_name(format!("{S_PARTIAL}{}", f_name.to_string_lossy()));
+        let file = File::create(&temp)?;
+        Ok(Self {
+            file: Some(file),
+            temp,
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Flush to disk and move the file to its final name
+    pub fn commit(mut self) -> io::Result<()> {
+        let file = self.file.take().expect("AtomicFile committed twice");
+        let synced = file
+            .sync_all()
+            .and_then(|_| rename(&self.temp, &self.target));
+        if synced.is_err() {
+            let _ = remove_file(&self.temp);
+        }
+        synced?;
+        sync_dir(&self.target);
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file
+            .as_mut()
+            .expect("AtomicFile used after commit")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file
+            .as_mut()
+            .expect("AtomicFile used after commit")
+            .flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = remove_file(&self.temp);
+        }
+    }
+}
+
+/// Persist the rename of `path` in its directory, best effort
+fn sync_dir(path: &Path) {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        if let Ok(dir) = File::open(dir) {
+            let _ = dir.sync_all();
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+}
+
+/// Remove temporary files left in `dir` by an interrupted run
+///
+/// Returns the removed (or with `dry_run`, the found) paths.
+pub fn clean_stale_partials(dir: &Path, dry_run: bool) -> io::Result<Vec<PathBuf>> {
+    let mut stale = vec![];
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(S_PARTIAL) {
+            if !dry_run {
+                remove_file(entry.path())?;
+            }
+            stale.push(entry.path());
+        }
+    }
+    Ok(stale)
+}
+
+/// Fill `buf` as far as the reader allows
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Writer counting the bytes that went through it
+pub(crate) struct CountingWriter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    /// Bytes written so far
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,33 +1,67 @@
-use crate::auxiliary::DirGuard;
-use crate::exec::{RET_DIR_ERROR, RET_ITEM_ERROR, entry_archive};
-use clap::{ArgAction, Parser};
-use glob::Pattern;
-#[cfg(feature = "regex")]
-use regex::Regex;
-use std::fs::read_dir;
-use std::path::{Path, PathBuf};
+use crate::archiver::{Archiver, BatchOptions, Mode};
+use crate::config::Settings;
+use crate::error::{Error, Operation, Result};
+use crate::events::Verbosity;
+use crate::filter::read_pattern_file;
+use crate::info::ArchiveInfo;
+use crate::list::ListStyle;
+use crate::manifest::ManifestFormat;
+use crate::params::Strategy;
+use crate::policy::{HardlinkPolicy, SpecialPolicy, SymlinkPolicy};
+use crate::report::OutputFormat;
+use crate::safety::{ConflictPolicy, ExistingPolicy, TarbombPolicy};
+use crate::seekable::{self, FRAME_SIZE_DEFAULT};
+use clap::{ArgAction, Parser, Subcommand};
+use serde::Serialize;
+use std::path::PathBuf;
 use std::process::ExitCode;
 
-/// Args for CLI use
+/// Command line, a command or the flat form of the earlier versions
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
-pub struct Args {
-    /// Directory to start processing
-    #[arg(value_name = "DIRECTORY")]
-    pub directory_start: PathBuf,
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    /// Preview what would be done without executing
-    #[arg(short = 'n', long)]
-    pub dryrun: bool,
+    #[command(flatten)]
+    pub legacy: Args,
+}
 
-    /// Exclude files matching glob pattern(s)
-    #[arg(short, long, value_name = "PATTERN", action = ArgAction::Append)]
-    pub exclude: Option<String>,
+/// What to do
+#[derive(Subcommand)]
+pub enum Command {
+    /// Pack every item of DIRECTORY into <name>.tar.zst
+    Compress(CompressArgs),
+    /// Unpack every *.tar.zst of DIRECTORY
+    Extract(ExtractArgs),
+    /// Check every *.tar.zst of DIRECTORY can be read back, without changing anything
+    Verify(VerifyArgs),
+    /// Print the entries of every *.tar.zst of DIRECTORY, without extracting them
+    List(ListArgs),
+    /// Show the format, sizes and settings of archives, without decoding them
+    Info(InfoArgs),
+    /// Put back the items of the compress run journaled in DIRECTORY
+    Restore(RestoreArgs),
+}
 
-    /// Exclude files matching regex pattern(s)
-    #[cfg(feature = "regex")]
-    #[arg(long, value_name = "PATTERN", action = ArgAction::Append)]
-    pub excludere: Option<String>,
+/// Flat form of the earlier versions, `zst_compress [-x] DIRECTORY`
+#[derive(clap::Args)]
+pub struct Args {
+    /// Directory to start processing
+    #[arg(value_name = "DIRECTORY", required = true)]
+    pub directory_start: Option<PathBuf>,
+
+    /// What to do when the archive of an item exists already: fail, skip,
+    /// overwrite, write a dated version beside it or merge the item into it
+    /// [default: fail]
+    #[arg(long, value_name = "POLICY", conflicts_with_all = ["extract", "verify_only", "list"])]
+    pub conflict: Option<ConflictPolicy>,
 
     /// Extract files (decompress mode)
     #[arg(short = 'x', long)]
@@ -37,227 +71,966 @@ pub struct Args {
     #[arg(short, long)]
     pub flag: bool,
 
-    /// Include files matching glob pattern(s)
-    /// [default: *]
-    #[arg(short, long, value_name = "PATTERN", action = ArgAction::Append)]
-    pub include: Option<String>,
+    /// Directory listing depth for logs
+    /// in *_archive_filelist.txt [default: 4]
+    #[arg(short, long, value_name = "LEVEL")]
+    pub leveldir: Option<u8>,
 
-    /// Include files matching regex pattern(s)
-    #[cfg(feature = "regex")]
-    #[arg(long, value_name = "PATTERN", action = ArgAction::Append)]
-    pub includere: Option<String>,
+    /// Print the entries of the existing *.tar.zst in DIRECTORY, or of the
+    /// single archive given as DIRECTORY, without extracting them
+    #[arg(long, conflicts_with_all = ["extract", "verify", "verify_only"])]
+    pub list: bool,
+
+    /// How --list prints the entries of each archive
+    #[arg(long, value_name = "STYLE", default_value = "tree", requires = "list")]
+    pub list_style: ListStyle,
+
+    /// Also write *_archived-manifest.<FORMAT> beside each archive, with the
+    /// exact size, mtime, mode, symlink target and SHA-256 of every entry;
+    /// the format goes after =, e.g. --manifest=csv
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "jsonl",
+        require_equals = true,
+        conflicts_with_all = ["extract", "verify_only", "list"]
+    )]
+    pub manifest: Option<ManifestFormat>,
+
+    /// Only extract the entries matching PATTERN, a path or a glob where *
+    /// also matches /; a directory brings everything below it. Repeat for
+    /// more patterns. Archives are kept, one with no matching entry fails
+    #[arg(long, value_name = "PATTERN", requires = "extract")]
+    pub member: Vec<String>,
+
+    /// Do not create the compression message file, even if a config file
+    /// asks for it
+    #[arg(long, conflicts_with = "flag")]
+    pub no_flag: bool,
+
+    /// Leave owner and group names out of the PAX records, even if a config
+    /// file asks for them
+    #[arg(long, conflicts_with = "owner_names")]
+    pub no_owner_names: bool,
+
+    /// Remove the originals after compression, even if a config file keeps them
+    #[arg(long, conflicts_with = "preserve")]
+    pub no_preserve: bool,
+
+    /// Skip the read-back of new archives, even if a config file asks for it
+    #[arg(long, conflicts_with = "verify")]
+    pub no_verify: bool,
+
+    /// Leave extended attributes out of new archives, even if a config file
+    /// asks for them
+    #[arg(long, conflicts_with = "xattrs")]
+    pub no_xattrs: bool,
+
+    /// Record owner and group names beside the uid and gid of each entry,
+    /// in PAX records
+    #[arg(long, conflicts_with_all = ["extract", "verify_only", "list"])]
+    pub owner_names: bool,
+
+    /// Keep original (do not delete) files after compression
+    #[arg(short, long)]
+    pub preserve: bool,
+
+    /// Take the defaults of the [profile.NAME] table of the config files
+    /// [env: ZST_COMPRESS_PROFILE]
+    #[arg(long, value_name = "NAME", conflicts_with_all = ["extract", "verify_only", "list"])]
+    pub profile: Option<String>,
+
+    /// Continue the run recorded in zst_journal.jsonl of the output
+    /// directory, zst_journal-extract.jsonl when extracting, finishing
+    /// half-done items
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Drop the first N components of entry paths on extraction, entries
+    /// with no more components are skipped
+    #[arg(long, value_name = "N", requires = "extract")]
+    pub strip_components: Option<usize>,
+
+    /// Output directory [default: DIRECTORY to start]
+    #[arg(short, long, value_name = "DIRECTORY")]
+    pub target: Option<PathBuf>,
+
+    /// Read back each new archive and compare it against the original
+    /// before the original is removed
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Only check the existing *.tar.zst in DIRECTORY and report corrupt ones
+    #[arg(long, conflicts_with_all = ["extract", "verify"])]
+    pub verify_only: bool,
+
+    #[command(flatten)]
+    pub embed: EmbedArgs,
+
+    #[command(flatten)]
+    pub entries: EntryArgs,
+
+    #[command(flatten)]
+    pub owner: OwnerArgs,
+
+    #[command(flatten)]
+    pub unpack: UnpackArgs,
+
+    #[command(flatten)]
+    pub items: ItemArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub zstd: ZstdArgs,
+}
+
+/// Options of `compress`
+#[derive(clap::Args)]
+pub struct CompressArgs {
+    /// Directory to start processing
+    #[arg(value_name = "DIRECTORY")]
+    pub directory: PathBuf,
+
+    /// What to do when the archive of an item exists already: fail, skip,
+    /// overwrite, write a dated version beside it or merge the item into it
+    /// [default: fail]
+    #[arg(long, value_name = "POLICY")]
+    pub conflict: Option<ConflictPolicy>,
+
+    /// Create compression message file on the compression
+    #[arg(short, long)]
+    pub flag: bool,
 
     /// Directory listing depth for logs
     /// in *_archive_filelist.txt [default: 4]
     #[arg(short, long, value_name = "LEVEL")]
     pub leveldir: Option<u8>,
 
-    /// Keep original (do not delete) files after compression
+    /// Also write *_archived-manifest.<FORMAT> beside each archive, with the
+    /// exact size, mtime, mode, symlink target and SHA-256 of every entry;
+    /// the format goes after =, e.g. --manifest=csv
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, default_missing_value = "jsonl",
+        require_equals = true)]
+    pub manifest: Option<ManifestFormat>,
+
+    /// Do not create the compression message file, even if a config file
+    /// asks for it
+    #[arg(long, conflicts_with = "flag")]
+    pub no_flag: bool,
+
+    /// Leave owner and group names out of the PAX records, even if a config
+    /// file asks for them
+    #[arg(long, conflicts_with = "owner_names")]
+    pub no_owner_names: bool,
+
+    /// Remove the originals after compression, even if a config file keeps them
+    #[arg(long, conflicts_with = "preserve")]
+    pub no_preserve: bool,
+
+    /// Skip the read-back of new archives, even if a config file asks for it
+    #[arg(long, conflicts_with = "verify")]
+    pub no_verify: bool,
+
+    /// Leave extended attributes out of new archives, even if a config file
+    /// asks for them
+    #[arg(long, conflicts_with = "xattrs")]
+    pub no_xattrs: bool,
+
+    /// Record owner and group names beside the uid and gid of each entry,
+    /// in PAX records
+    #[arg(long)]
+    pub owner_names: bool,
+
+    /// Keep the originals after compression
     #[arg(short, long)]
     pub preserve: bool,
 
-    /// Suppress output except errors (NO FUNCTION)
+    /// Take the defaults of the [profile.NAME] table of the config files
+    /// [env: ZST_COMPRESS_PROFILE]
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// Continue the run recorded in zst_journal.jsonl of the output
+    /// directory, finishing half-done items
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Output directory [default: DIRECTORY]
+    #[arg(short, long, value_name = "DIRECTORY")]
+    pub target: Option<PathBuf>,
+
+    /// Read back each new archive and compare it against the original
+    /// before the original is removed
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Record extended attributes in PAX records, POSIX ACLs and SELinux
+    /// labels included
+    #[arg(long)]
+    pub xattrs: bool,
+
+    #[command(flatten)]
+    pub embed: EmbedArgs,
+
+    #[command(flatten)]
+    pub entries: EntryArgs,
+
+    #[command(flatten)]
+    pub items: ItemArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    #[command(flatten)]
+    pub zstd: ZstdArgs,
+}
+
+/// Options of `extract`
+#[derive(clap::Args)]
+pub struct ExtractArgs {
+    /// Directory to find the archives in, or a single archive
+    #[arg(value_name = "DIRECTORY")]
+    pub directory: PathBuf,
+
+    /// Only extract the entries matching PATTERN, a path or a glob where *
+    /// also matches /; a directory brings everything below it. Repeat for
+    /// more patterns. Archives are kept, one with no matching entry fails
+    #[arg(long, value_name = "PATTERN")]
+    pub member: Vec<String>,
+
+    /// Keep the archives after extraction
     #[arg(short, long)]
-    pub quiet: bool,
+    pub preserve: bool,
 
-    /// Output directory [default: DIRECTORY to start]
+    /// Continue the run recorded in zst_journal-extract.jsonl of the
+    /// output directory, unpacking half-done items again
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Drop the first N components of entry paths, entries with no more
+    /// components are skipped
+    #[arg(long, value_name = "N")]
+    pub strip_components: Option<usize>,
+
+    /// Output directory [default: DIRECTORY]
     #[arg(short, long, value_name = "DIRECTORY")]
     pub target: Option<PathBuf>,
 
-    /// Show detailed progress information (NO FUNCTION)
+    #[command(flatten)]
+    pub owner: OwnerArgs,
+
+    #[command(flatten)]
+    pub unpack: UnpackArgs,
+
+    #[command(flatten)]
+    pub items: ItemArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Options of `verify`
+#[derive(clap::Args)]
+pub struct VerifyArgs {
+    /// Directory to find the archives in, or a single archive
+    #[arg(value_name = "DIRECTORY")]
+    pub directory: PathBuf,
+
+    #[command(flatten)]
+    pub items: ItemArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Options of `list`
+#[derive(clap::Args)]
+pub struct ListArgs {
+    /// Directory to find the archives in, or a single archive
+    #[arg(value_name = "DIRECTORY")]
+    pub directory: PathBuf,
+
+    /// How the entries of each archive are printed
+    #[arg(long, value_name = "STYLE", default_value = "tree")]
+    pub style: ListStyle,
+
+    #[command(flatten)]
+    pub items: ItemArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Options of `info`
+#[derive(clap::Args)]
+pub struct InfoArgs {
+    /// Archives to describe
+    #[arg(value_name = "ARCHIVE", required = true)]
+    pub archives: Vec<PathBuf>,
+
+    /// Print lines for people, or one JSON record per archive, with all
+    /// embedded data
+    #[arg(long, value_name = "FORMAT", default_value = "human")]
+    pub format: OutputFormat,
+
+    /// Also print the embedded listing
+    #[arg(long)]
+    pub listing: bool,
+
+    /// Also print the embedded manifest, one JSON object per line
+    #[arg(long)]
+    pub manifest: bool,
+}
+
+/// Options of `restore`
+#[derive(clap::Args)]
+pub struct RestoreArgs {
+    /// Output directory of the compress run, holding its zst_journal.jsonl
+    #[arg(value_name = "DIRECTORY")]
+    pub directory: PathBuf,
+
+    /// Keep the archives after extraction
+    #[arg(short, long)]
+    pub preserve: bool,
+
+    #[command(flatten)]
+    pub owner: OwnerArgs,
+
+    #[command(flatten)]
+    pub unpack: UnpackArgs,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+/// Which recorded attributes extraction gives back
+#[derive(clap::Args)]
+pub struct OwnerArgs {
+    /// Leave extracted entries to the extracting user, the default when not root
+    #[arg(long)]
+    pub no_same_owner: bool,
+
+    /// Restore the recorded uid and gid, ignoring recorded owner names
+    #[arg(long)]
+    pub numeric_owner: bool,
+
+    /// Give extracted entries their recorded owner, the default as root
+    #[arg(long, conflicts_with = "no_same_owner")]
+    pub same_owner: bool,
+
+    /// Keep the setuid, setgid and sticky bits of extracted entries
+    #[arg(long)]
+    pub same_permissions: bool,
+
+    /// Restore the recorded extended attributes, POSIX ACLs and SELinux labels included
+    #[arg(long)]
+    pub xattrs: bool,
+}
+
+/// What extraction does with tarbombs and files already there
+#[derive(clap::Args)]
+pub struct UnpackArgs {
+    /// Fail an archive whose entries would replace existing files, before unpacking anything
+    /// when an index or embedded manifest lists them
+    #[arg(long, conflicts_with_all = ["overwrite", "skip_existing", "keep_both"])]
+    pub fail_existing: bool,
+
+    /// Unpack entries whose destination exists under the first free name, e.g. notes.1.txt
+    #[arg(long, conflicts_with_all = ["overwrite", "skip_existing"])]
+    pub keep_both: bool,
+
+    /// Replace files already at the destination of an entry, the default
+    #[arg(long, conflicts_with = "skip_existing")]
+    pub overwrite: bool,
+
+    /// Keep files already at the destination of an entry and skip the entry
+    #[arg(long)]
+    pub skip_existing: bool,
+
+    /// Unpack archives with entries outside <name>/ into <name>/, or refuse them [default: redirect]
+    #[arg(long, value_name = "POLICY")]
+    pub tarbomb: Option<TarbombPolicy>,
+}
+
+/// What goes inside each archive besides the item
+#[derive(clap::Args)]
+pub struct EmbedArgs {
+    /// Put the listing, the manifest and metadata (creator, host, tool
+    /// version, level, source path) in a skippable frame at the start of
+    /// each archive, shown by `info`
+    #[arg(long)]
+    pub embed: bool,
+
+    /// Add KEY=VALUE to the embedded metadata, repeated
+    #[arg(long, value_name = "KEY=VALUE", requires = "embed", value_parser = parse_key_value)]
+    pub meta: Vec<(String, String)>,
+
+    /// Do not embed anything in the archives, even if a config file asks for it
+    #[arg(long, conflicts_with = "embed")]
+    pub no_embed: bool,
+
+    /// Free-form note for the embedded metadata
+    #[arg(long, value_name = "TEXT", requires = "embed")]
+    pub note: Option<String>,
+}
+
+/// How entries that are not plain files or directories are archived
+#[derive(clap::Args)]
+pub struct EntryArgs {
+    /// Store further names of a file as hard links, or with its contents again [default: keep]
+    #[arg(long, value_name = "POLICY")]
+    pub hardlinks: Option<HardlinkPolicy>,
+
+    /// Store sparse files with their holes written out as zeros
+    #[arg(long)]
+    pub no_sparse: bool,
+
+    /// Skip FIFOs, sockets and device nodes, or store them [default: skip]
+    #[arg(long, value_name = "POLICY")]
+    pub special: Option<SpecialPolicy>,
+
+    /// Store symlinks as links, or archive what they point to [default: store]
+    #[arg(long, value_name = "POLICY")]
+    pub symlinks: Option<SymlinkPolicy>,
+}
+
+/// Which items of DIRECTORY a batch works on
+#[derive(clap::Args)]
+pub struct ItemArgs {
+    /// Take the items N levels below DIRECTORY, their results going to the
+    /// same place below the output directory; above 1, commands reading
+    /// archives take *.tar.zst at any depth [default: 1]
+    #[arg(short, long, value_name = "N")]
+    pub depth: Option<usize>,
+
+    /// Exclude files matching glob pattern(s), repeated or comma-separated
+    #[arg(short, long, value_name = "PATTERN", action = ArgAction::Append, value_delimiter = ',')]
+    pub exclude: Vec<String>,
+
+    /// Exclude files matching the glob patterns listed in FILE, one per line
+    #[arg(long, value_name = "FILE", action = ArgAction::Append)]
+    pub exclude_from: Vec<PathBuf>,
+
+    /// Exclude files matching regex pattern(s), repeated
+    #[cfg(feature = "regex")]
+    #[arg(long, value_name = "PATTERN", action = ArgAction::Append)]
+    pub excludere: Vec<String>,
+
+    /// Include files matching glob pattern(s), repeated or comma-separated
+    /// [default: *]
+    #[arg(short, long, value_name = "PATTERN", action = ArgAction::Append, value_delimiter = ',')]
+    pub include: Vec<String>,
+
+    /// Include files matching the glob patterns listed in FILE, one per line
+    #[arg(long, value_name = "FILE", action = ArgAction::Append)]
+    pub include_from: Vec<PathBuf>,
+
+    /// Include files matching regex pattern(s), repeated
+    #[cfg(feature = "regex")]
+    #[arg(long, value_name = "PATTERN", action = ArgAction::Append)]
+    pub includere: Vec<String>,
+}
+
+/// How a batch runs and what it prints
+#[derive(clap::Args)]
+pub struct RunArgs {
+    /// Preview what would be done without executing
+    #[arg(short = 'n', long)]
+    pub dryrun: bool,
+
+    /// Print progress for people, or one JSON record per item and a summary
+    #[arg(long, value_name = "FORMAT", default_value = "human")]
+    pub format: OutputFormat,
+
+    /// Number of items processed at the same time [default: 1]
+    #[arg(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+
+    /// Start without checking free space and write permissions first
+    #[arg(long)]
+    pub no_preflight: bool,
+
+    /// Suppress output except errors
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Also write the JSON records to FILE
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<PathBuf>,
+
+    /// Total thread budget shared by parallel items and their zstd workers
+    /// [default: available CPUs]
+    #[arg(long, value_name = "N")]
+    pub threads: Option<usize>,
+
+    /// Also show tar entries, removals, timings, ratios and thread counts
     #[arg(short, long)]
     pub verbose: bool,
+}
 
-    /// Zstandard compress level, 1(fastest) to 22(smallest);
-    /// [default: 5]
-    #[arg(short, long, value_name = "LEVEL")]
+/// Settings of the zstd encoder
+#[derive(clap::Args)]
+pub struct ZstdArgs {
+    /// Compress with the zstd dictionary in FILE, kept beside the archives
+    /// as zst_dict-<ID>.dict for extraction
+    #[arg(long, value_name = "FILE", conflicts_with = "train_dict")]
+    pub dict: Option<PathBuf>,
+
+    /// Largest size of a trained dictionary [default: 112640]
+    #[arg(long, value_name = "BYTES", requires = "train_dict")]
+    pub dict_size: Option<usize>,
+
+    /// Uncompressed size of each zstd frame of a seekable archive, at least
+    /// 65536 [default: 8388608]
+    #[arg(long, value_name = "BYTES", requires = "seekable")]
+    pub frame_size: Option<usize>,
+
+    /// Long-distance matching with a window of 2^WLOG bytes, for large files
+    /// with repetitions far apart [default WLOG: 27]
+    #[arg(long, value_name = "WLOG", num_args = 0..=1, default_missing_value = "27")]
+    pub long: Option<u32>,
+
+    /// Write each archive as a single zstd frame, even if a config file asks
+    /// for seekable archives
+    #[arg(long, conflicts_with = "seekable")]
+    pub no_seekable: bool,
+
+    /// Keep zstd levels at 19 or below, even if a config file allows more
+    #[arg(long, conflicts_with = "ultra")]
+    pub no_ultra: bool,
+
+    /// Write each archive as independent zstd frames followed by an index
+    /// of its entries, so single entries can be read without decoding
+    /// everything before them and frames can be decoded in parallel
+    #[arg(long)]
+    pub seekable: bool,
+
+    /// Match finder of the zstd encoder, overriding the one of the level
+    #[arg(long, value_name = "STRATEGY")]
+    pub strategy: Option<Strategy>,
+
+    /// Train a zstd dictionary from samples of the selected items and
+    /// compress every item with it, for many small similar items
+    #[arg(long)]
+    pub train_dict: bool,
+
+    /// Allow zstd levels above 19, up to 22, at the cost of much more memory
+    #[arg(long)]
+    pub ultra: bool,
+
+    /// Window of 2^WLOG bytes for zstd, overriding the one of the level or
+    /// of --long
+    #[arg(long, value_name = "WLOG")]
+    pub window_log: Option<u32>,
+
+    /// Zstandard compress level, negative (fastest) to 19, or 22 (smallest)
+    /// with --ultra; [default: 5]
+    #[arg(short, long, value_name = "LEVEL", allow_negative_numbers = true)]
     pub zstdlevel: Option<i32>,
 }
+
 /// Do the cli parsing
 pub fn cli() -> ExitCode {
-    let args = Args::parse();
-    match batch_archive(args) {
+    let cli = Cli::parse();
+    let ret = match cli.command {
+        Some(Command::Info(args)) => info(args),
+        Some(command) => command.try_into().and_then(run_batch),
+        None => batch_archive(cli.legacy),
+    };
+    match ret {
         Ok(()) => ExitCode::SUCCESS,
-        Err(ret) => ExitCode::from(ret),
+        Err(e) => {
+            eprintln!("出错了! {e}");
+            ExitCode::from(e.exit_code())
+        }
     }
 }
 
 /// Compress or decompress all items in a folder
-pub fn batch_archive(args: Args) -> Result<(), u8> {
-    let mut ret = 0;
-    let level_tree = args.leveldir.unwrap_or(4);
-    let compress = !args.extract;
-
-    let start_dir = &args.directory_start;
-    let _guard = DirGuard::new(start_dir)?;
-    let target_dir = if let Some(target) = &args.target {
-        Path::new(target)
-    } else {
-        start_dir.as_path()
-    };
-    // Walk through videos
-    match read_dir(start_dir) {
-        Ok(entries) => {
-            let entries: Vec<_> = entries.collect();
-            let mut valid_entries: Vec<_> = vec![];
-            for entry_result in entries.into_iter() {
-                match entry_result {
-                    Ok(entry) => {
-                        let file_path = entry.path();
-                        #[cfg(feature = "regex")]
-                        {
-                            if should_process_file_with_regex(
-                                &file_path,
-                                &args.include,
-                                &args.exclude,
-                                &args.includere,
-                                &args.excludere,
-                            ) {
-                                valid_entries.push(entry.path());
-                            }
-                        }
-                        #[cfg(not(feature = "regex"))]
-                        {
-                            if should_process_file_no_regex(
-                                &file_path,
-                                &args.include,
-                                &args.exclude,
-                            ) {
-                                valid_entries.push(entry.path());
-                            }
+pub fn batch_archive(args: Args) -> Result<()> {
+    run_batch(args.try_into()?)
+}
+
+/// Run a batch, failing if any item failed
+fn run_batch(options: BatchOptions) -> Result<()> {
+    let summary = Archiver::new(options).run()?;
+    match summary.failed() {
+        0 => Ok(()),
+        failed => Err(Error::Batch {
+            failed,
+            total: summary.total(),
+        }),
+    }
+}
+
+/// Record of one archive for `info --format json`
+#[derive(Serialize)]
+struct InfoRecord<'a> {
+    record: &'static str,
+    #[serde(flatten)]
+    info: &'a ArchiveInfo,
+}
+
+/// Print what each archive tells about itself
+fn info(args: InfoArgs) -> Result<()> {
+    for archive in &args.archives {
+        let info =
+            ArchiveInfo::read(archive).map_err(|e| Error::io(Operation::ReadInfo, archive, e))?;
+        match args.format {
+            OutputFormat::Human => {
+                print!("{}", info.render());
+                let embedded = info.embedded.unwrap_or_default();
+                if args.listing {
+                    print!("{}", embedded.listing.unwrap_or_default());
+                }
+                if args.manifest {
+                    for entry in &embedded.manifest {
+                        if let Ok(line) = serde_json::to_string(entry) {
+                            println!("{line}");
                         }
                     }
-                    Err(e) => {
-                        eprintln!("出错了! Error: {e}");
-                        return Err(RET_DIR_ERROR);
-                    }
                 }
             }
-            let total_items = valid_entries.len();
-            if total_items < 1 {
-                eprintln!("No item in {:?} to process.", &start_dir)
+            OutputFormat::Json => match serde_json::to_string(&InfoRecord {
+                record: "info",
+                info: &info,
+            }) {
+                Ok(line) => println!("{line}"),
+                Err(e) => eprintln!("出错了! Failed to write record: {e}"),
+            },
+        }
+    }
+    Ok(())
+}
+
+impl TryFrom<Command> for BatchOptions {
+    type Error = Error;
+
+    /// Fails if a pattern file, a config file or the journal to restore cannot be read
+    fn try_from(command: Command) -> Result<Self> {
+        match command {
+            Command::Compress(args) => {
+                let settings = Settings::load(&args.directory, args.profile.as_deref())?;
+                let mut options = settings
+                    .apply(BatchOptions::new(&args.directory))
+                    .mode(Mode::Compress)
+                    .resume(args.resume);
+                if let Some(conflict) = args.conflict {
+                    options = options.conflict(conflict);
+                }
+                if let Some(leveldir) = args.leveldir {
+                    options = options.listing_depth(leveldir);
+                }
+                if let Some(format) = args.manifest {
+                    options = options.manifest(format);
+                }
+                if args.owner_names || args.no_owner_names {
+                    options = options.owner_names(args.owner_names);
+                }
+                if args.xattrs || args.no_xattrs {
+                    options = options.xattrs(args.xattrs);
+                }
+                if args.preserve || args.no_preserve {
+                    options = options.preserve(args.preserve);
+                }
+                if args.flag || args.no_flag {
+                    options = options.flag(args.flag);
+                }
+                if args.verify || args.no_verify {
+                    options = options.verify(args.verify);
+                }
+                if let Some(target) = args.target {
+                    options = options.target(target);
+                }
+                let options = args
+                    .zstd
+                    .apply(args.embed.apply(args.entries.apply(options)));
+                seekable::check_frame_size(options.frame_size)?;
+                Ok(args.run.apply(args.items.apply(options)?))
             }
-            for (current_item, entry_path) in valid_entries.into_iter().enumerate() {
-                if entry_archive(
-                    entry_path.as_path(),
-                    compress,
-                    args.preserve,
-                    args.flag,
-                    target_dir,
-                    level_tree,
-                    args.zstdlevel.unwrap_or(5_i32),
-                    current_item + 1,
-                    total_items,
-                    args.dryrun,
-                ) != Ok(())
-                {
-                    ret = RET_ITEM_ERROR
+            Command::Extract(args) => {
+                let mut options = BatchOptions::new(args.directory)
+                    .mode(Mode::Extract)
+                    .preserve(args.preserve)
+                    .resume(args.resume);
+                if let Some(n) = args.strip_components {
+                    options = options.strip_components(n);
+                }
+                if let Some(target) = args.target {
+                    options = options.target(target);
                 }
+                let options = args.member.into_iter().fold(options, BatchOptions::member);
+                let options = args.unpack.apply(args.owner.apply(options));
+                Ok(args.run.apply(args.items.apply(options)?))
+            }
+            Command::Verify(args) => {
+                let options = BatchOptions::new(args.directory).mode(Mode::Verify);
+                Ok(args.run.apply(args.items.apply(options)?))
+            }
+            Command::List(args) => {
+                let options = BatchOptions::new(args.directory)
+                    .mode(Mode::List)
+                    .list_style(args.style);
+                Ok(args.run.apply(args.items.apply(options)?))
             }
+            Command::Restore(args) => {
+                let options = BatchOptions::restore(args.directory)?.preserve(args.preserve);
+                Ok(args.run.apply(args.unpack.apply(args.owner.apply(options))))
+            }
+            Command::Info(_) => Err(Error::InvalidOption(
+                "info does not run a batch".to_string(),
+            )),
         }
-        Err(e) => eprintln!("出错了! Error: {e}"),
-    };
-
-    match ret {
-        0 => Ok(()),
-        _ => Err(ret),
     }
 }
 
-/// Check if a file path matches the include and exclude patterns
-/// Supports both glob patterns and regular expressions
-#[cfg(feature = "regex")]
-fn should_process_file_with_regex(
-    file_path: &Path,
-    include_patterns: &Option<String>,
-    exclude_patterns: &Option<String>,
-    include_regex_patterns: &Option<String>,
-    exclude_regex_patterns: &Option<String>,
-) -> bool {
-    let file_name = file_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("");
-
-    // Check exclude glob patterns first
-    if let Some(exclude) = exclude_patterns
-        && let Ok(pattern) = Pattern::new(exclude)
-        && pattern.matches(file_name)
-    {
-        return false;
-    }
+impl TryFrom<Args> for BatchOptions {
+    type Error = Error;
 
-    // Check exclude regex patterns
-    if let Some(exclude_regex) = exclude_regex_patterns
-        && let Ok(regex) = Regex::new(exclude_regex)
-        && regex.is_match(file_name)
-    {
-        return false;
+    /// Fails if a pattern file or a config file cannot be read
+    fn try_from(args: Args) -> Result<Self> {
+        let directory = args
+            .directory_start
+            .ok_or_else(|| Error::InvalidOption("no DIRECTORY given".to_string()))?;
+        let mode = match (args.extract, args.verify_only, args.list) {
+            (true, _, _) => Mode::Extract,
+            (_, true, _) => Mode::Verify,
+            (_, _, true) => Mode::List,
+            _ => Mode::Compress,
+        };
+        let mut options = BatchOptions::new(&directory);
+        if mode == Mode::Compress {
+            options = Settings::load(&directory, args.profile.as_deref())?.apply(options);
+        }
+        let mut options = options
+            .mode(mode)
+            .list_style(args.list_style)
+            .resume(args.resume);
+        if let Some(conflict) = args.conflict {
+            options = options.conflict(conflict);
+        }
+        if let Some(leveldir) = args.leveldir {
+            options = options.listing_depth(leveldir);
+        }
+        if let Some(format) = args.manifest {
+            options = options.manifest(format);
+        }
+        if args.owner_names || args.no_owner_names {
+            options = options.owner_names(args.owner_names);
+        }
+        if args.no_xattrs {
+            options = options.xattrs(false);
+        }
+        if args.preserve || args.no_preserve {
+            options = options.preserve(args.preserve);
+        }
+        if args.flag || args.no_flag {
+            options = options.flag(args.flag);
+        }
+        if args.verify || args.no_verify {
+            options = options.verify(args.verify);
+        }
+        if let Some(n) = args.strip_components {
+            options = options.strip_components(n);
+        }
+        if let Some(target) = args.target {
+            options = options.target(target);
+        }
+        options = args.member.into_iter().fold(options, BatchOptions::member);
+        let options = args
+            .entries
+            .apply(args.unpack.apply(args.owner.apply(options)));
+        let options = args.zstd.apply(args.embed.apply(options));
+        seekable::check_frame_size(options.frame_size)?;
+        Ok(args.run.apply(args.items.apply(options)?))
     }
+}
 
-    // Check include patterns - if any include pattern matches, process the file
-    let mut should_include = false;
-
-    // Check include glob patterns (default to "*" if None)
-    if let Some(include) = include_patterns {
-        if let Ok(pattern) = Pattern::new(include)
-            && pattern.matches(file_name)
-        {
-            should_include = true;
+impl OwnerArgs {
+    fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        if self.same_owner || self.no_same_owner {
+            options = options.same_owner(self.same_owner);
         }
-    } else {
-        // Default behavior: include everything if no include pattern specified
-        should_include = true;
+        if self.numeric_owner {
+            options = options.numeric_owner(true);
+        }
+        if self.same_permissions {
+            options = options.same_permissions(true);
+        }
+        if self.xattrs {
+            options = options.xattrs(true);
+        }
+        options
     }
+}
 
-    // Check include regex patterns
-    if let Some(include_regex) = include_regex_patterns
-        && let Ok(regex) = Regex::new(include_regex)
-        && regex.is_match(file_name)
-    {
-        should_include = true;
+impl UnpackArgs {
+    fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        let existing = match self {
+            Self {
+                overwrite: true, ..
+            } => Some(ExistingPolicy::Overwrite),
+            Self {
+                skip_existing: true,
+                ..
+            } => Some(ExistingPolicy::Skip),
+            Self {
+                keep_both: true, ..
+            } => Some(ExistingPolicy::KeepBoth),
+            Self {
+                fail_existing: true,
+                ..
+            } => Some(ExistingPolicy::Fail),
+            _ => None,
+        };
+        if let Some(existing) = existing {
+            options = options.existing(existing);
+        }
+        if let Some(tarbomb) = self.tarbomb {
+            options = options.tarbomb(tarbomb);
+        }
+        options
     }
+}
 
-    // If both include and include regex are None, default to "*"
-    if include_patterns.is_none() && include_regex_patterns.is_none() {
-        should_include = true;
+impl EntryArgs {
+    fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        if let Some(hardlinks) = self.hardlinks {
+            options = options.hardlinks(hardlinks);
+        }
+        if self.no_sparse {
+            options = options.sparse(false);
+        }
+        if let Some(special) = self.special {
+            options = options.special(special);
+        }
+        if let Some(symlinks) = self.symlinks {
+            options = options.symlinks(symlinks);
+        }
+        options
     }
+}
 
-    should_include
+impl EmbedArgs {
+    fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        if self.embed || self.no_embed {
+            options = options.embed(self.embed);
+        }
+        if let Some(note) = self.note {
+            options = options.metadata("note", note);
+        }
+        self.meta
+            .into_iter()
+            .fold(options, |options, (key, value)| {
+                options.metadata(key, value)
+            })
+    }
 }
 
-/// Check if a file path matches the include and exclude patterns (no regex support)
-/// Only supports glob patterns
-#[cfg(not(feature = "regex"))]
-fn should_process_file_no_regex(
-    file_path: &Path,
-    include_patterns: &Option<String>,
-    exclude_patterns: &Option<String>,
-) -> bool {
-    let file_name = file_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("");
-
-    // Check exclude glob patterns first
-    if let Some(exclude) = exclude_patterns
-        && let Ok(pattern) = Pattern::new(exclude)
-        && pattern.matches(file_name)
-    {
-        return false;
+/// Split `KEY=VALUE` of `--meta`
+fn parse_key_value(pair: &str) -> std::result::Result<(String, String), String> {
+    match pair.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected KEY=VALUE".to_string()),
     }
+}
 
-    // Check include glob patterns (default to "*" if None)
-    if let Some(include) = include_patterns {
-        if let Ok(pattern) = Pattern::new(include)
-            && pattern.matches(file_name)
+impl ItemArgs {
+    /// Fails if a pattern file cannot be read
+    fn apply(self, mut options: BatchOptions) -> Result<BatchOptions> {
+        if let Some(depth) = self.depth {
+            options = options.depth(depth);
+        }
+        for path in &self.include_from {
+            options = read_pattern_file(path)?
+                .into_iter()
+                .fold(options, BatchOptions::include);
+        }
+        for path in &self.exclude_from {
+            options = read_pattern_file(path)?
+                .into_iter()
+                .fold(options, BatchOptions::exclude);
+        }
+        options = self
+            .include
+            .into_iter()
+            .fold(options, BatchOptions::include);
+        options = self
+            .exclude
+            .into_iter()
+            .fold(options, BatchOptions::exclude);
+        #[cfg(feature = "regex")]
         {
-            return true;
+            options = self
+                .includere
+                .into_iter()
+                .fold(options, BatchOptions::include_regex);
+            options = self
+                .excludere
+                .into_iter()
+                .fold(options, BatchOptions::exclude_regex);
         }
-    } else {
-        // Default behavior: include everything if no include pattern specified
-        return true;
+        Ok(options)
     }
+}
 
-    false
+impl RunArgs {
+    fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        options = options.dry_run(self.dryrun).format(self.format).verbosity(
+            match (self.quiet, self.verbose) {
+                (true, _) => Verbosity::Quiet,
+                (_, true) => Verbosity::Verbose,
+                _ => Verbosity::Normal,
+            },
+        );
+        if let Some(jobs) = self.jobs {
+            options = options.jobs(jobs);
+        }
+        if self.no_preflight {
+            options = options.preflight(false);
+        }
+        if let Some(threads) = self.threads {
+            options = options.threads(threads);
+        }
+        if let Some(report) = self.report {
+            options = options.report(report);
+        }
+        options
+    }
+}
+
+impl ZstdArgs {
+    fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        if let Some(level) = self.zstdlevel {
+            options = options.level(level);
+        }
+        if self.ultra || self.no_ultra {
+            options = options.ultra(self.ultra);
+        }
+        if let Some(long) = self.long {
+            options = options.long(long);
+        }
+        if let Some(window_log) = self.window_log {
+            options = options.window_log(window_log);
+        }
+        if let Some(strategy) = self.strategy {
+            options = options.strategy(strategy);
+        }
+        if let Some(dict) = self.dict {
+            options = options.dictionary(dict);
+        }
+        if self.train_dict {
+            options = options.train_dictionary(self.dict_size.unwrap_or(112_640));
+        }
+        if self.seekable {
+            options = options.seekable(self.frame_size.unwrap_or(FRAME_SIZE_DEFAULT));
+        }
+        if self.no_seekable {
+            options = options.single_frame();
+        }
+        options
+    }
 }
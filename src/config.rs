@@ -0,0 +1,361 @@
+use crate::archiver::BatchOptions;
+use crate::error::{Error, Operation, Result};
+use crate::manifest::ManifestFormat;
+use crate::params::Strategy;
+use crate::policy::{HardlinkPolicy, SpecialPolicy, SymlinkPolicy};
+use crate::safety::ConflictPolicy;
+use crate::seekable::FRAME_SIZE_DEFAULT;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Config file read from the processed directory
+pub static S_CONFIG: &str = "zst_compress.toml";
+
+/// Prefix of the environment variables holding settings, e.g. `ZST_COMPRESS_LEVEL`
+pub static ENV_PREFIX: &str = "ZST_COMPRESS_";
+
+/// Environment variable naming the profile when `--profile` is not given
+pub static ENV_PROFILE: &str = "ZST_COMPRESS_PROFILE";
+
+/// Keys of [`Settings`], the only `ZST_COMPRESS_<KEY>` variables read
+const KEYS: [&str; 26] = [
+    "level",
+    "leveldir",
+    "manifest",
+    "embed",
+    "target",
+    "include",
+    "exclude",
+    "depth",
+    "jobs",
+    "threads",
+    "preserve",
+    "flag",
+    "verify",
+    "conflict",
+    "owner-names",
+    "xattrs",
+    "symlinks",
+    "hardlinks",
+    "sparse",
+    "special",
+    "ultra",
+    "long",
+    "window-log",
+    "strategy",
+    "seekable",
+    "frame-size",
+];
+
+/// Settings whose values are lists, comma-separated in the environment
+const LIST_KEYS: [&str; 2] = ["include", "exclude"];
+
+/// Defaults of a compress run, from a config file, a profile or the environment
+///
+/// Keys are the long CLI options: `level`, `leveldir`, `manifest`, `embed`, `target`,
+/// `include`, `exclude`, `depth`, `jobs`, `threads`, `preserve`, `flag`, `verify`,
+/// `conflict`, `owner-names`, `xattrs`, `symlinks`, `hardlinks`, `sparse`, `special`,
+/// `ultra`, `long`, `window-log`, `strategy`, `seekable` and `frame-size`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    pub level: Option<i32>,
+    pub leveldir: Option<u8>,
+    pub manifest: Option<ManifestFormat>,
+    pub embed: Option<bool>,
+    pub target: Option<PathBuf>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub depth: Option<usize>,
+    pub jobs: Option<usize>,
+    pub threads: Option<usize>,
+    pub preserve: Option<bool>,
+    pub flag: Option<bool>,
+    pub verify: Option<bool>,
+    pub conflict: Option<ConflictPolicy>,
+    pub owner_names: Option<bool>,
+    pub xattrs: Option<bool>,
+    pub symlinks: Option<SymlinkPolicy>,
+    pub hardlinks: Option<HardlinkPolicy>,
+    pub sparse: Option<bool>,
+    pub special: Option<SpecialPolicy>,
+    pub ultra: Option<bool>,
+    pub long: Option<u32>,
+    pub window_log: Option<u32>,
+    pub strategy: Option<Strategy>,
+    pub seekable: Option<bool>,
+    pub frame_size: Option<usize>,
+}
+
+impl Settings {
+    /// Settings of a compress run of `dir`, lowest precedence first:
+    ///
+    /// 1. the user config file, see [`user_config_path`]
+    /// 2. `zst_compress.toml` in `dir`
+    /// 3. the `[profile.<name>]` table of `profile`, or of `ZST_COMPRESS_PROFILE`,
+    ///    from the user config file and then from the one in `dir`
+    /// 4. `ZST_COMPRESS_<KEY>` environment variables, e.g. `ZST_COMPRESS_WINDOW_LOG`
+    ///
+    /// The command line goes above all of them. Each layer replaces single
+    /// values and adds to the `include` and `exclude` lists.
+    pub fn load(dir: &Path, profile: Option<&str>) -> Result<Self> {
+        let files: Vec<_> = user_config_path()
+            .into_iter()
+            .chain([dir.join(S_CONFIG)])
+            .filter_map(|path| ConfigFile::read(&path).transpose())
+            .collect::<Result<_>>()?;
+
+        let mut settings = Settings::default();
+        for file in &files {
+            settings.merge(file.defaults.clone());
+        }
+        let profile = match profile {
+            Some(profile) => Some(profile.to_string()),
+            None => env::var(ENV_PROFILE).ok(),
+        };
+        if let Some(profile) = profile {
+            let mut found = false;
+            for file in &files {
+                if let Some(table) = file.profiles.get(&profile) {
+                    settings.merge(file.settings(table.clone(), Some(&profile))?);
+                    found = true;
+                }
+            }
+            if !found {
+                return Err(Error::InvalidOption(format!(
+                    "no profile {profile:?} in the config files"
+                )));
+            }
+        }
+        settings.merge(from_env()?);
+        Ok(settings)
+    }
+
+    /// Take the values set in `other`, adding to the lists
+    pub fn merge(&mut self, other: Settings) {
+        macro_rules! replace {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+        replace!(
+            level,
+            leveldir,
+            manifest,
+            embed,
+            target,
+            depth,
+            jobs,
+            threads,
+            preserve,
+            flag,
+            verify,
+            conflict,
+            owner_names,
+            xattrs,
+            symlinks,
+            hardlinks,
+            sparse,
+            special,
+            ultra,
+            long,
+            window_log,
+            strategy,
+            seekable,
+            frame_size
+        );
+        self.include.extend(other.include);
+        self.exclude.extend(other.exclude);
+    }
+
+    /// Use these settings as the defaults of `options`
+    pub fn apply(self, mut options: BatchOptions) -> BatchOptions {
+        if let Some(level) = self.level {
+            options = options.level(level);
+        }
+        if let Some(leveldir) = self.leveldir {
+            options = options.listing_depth(leveldir);
+        }
+        if let Some(format) = self.manifest {
+            options = options.manifest(format);
+        }
+        if let Some(embed) = self.embed {
+            options = options.embed(embed);
+        }
+        if let Some(target) = self.target {
+            options = options.target(target);
+        }
+        if let Some(depth) = self.depth {
+            options = options.depth(depth);
+        }
+        if let Some(jobs) = self.jobs {
+            options = options.jobs(jobs);
+        }
+        if let Some(threads) = self.threads {
+            options = options.threads(threads);
+        }
+        if let Some(preserve) = self.preserve {
+            options = options.preserve(preserve);
+        }
+        if let Some(flag) = self.flag {
+            options = options.flag(flag);
+        }
+        if let Some(verify) = self.verify {
+            options = options.verify(verify);
+        }
+        if let Some(conflict) = self.conflict {
+            options = options.conflict(conflict);
+        }
+        if let Some(owner_names) = self.owner_names {
+            options = options.owner_names(owner_names);
+        }
+        if let Some(xattrs) = self.xattrs {
+            options = options.xattrs(xattrs);
+        }
+        if let Some(symlinks) = self.symlinks {
+            options = options.symlinks(symlinks);
+        }
+        if let Some(hardlinks) = self.hardlinks {
+            options = options.hardlinks(hardlinks);
+        }
+        if let Some(sparse) = self.sparse {
+            options = options.sparse(sparse);
+        }
+        if let Some(special) = self.special {
+            options = options.special(special);
+        }
+        if let Some(ultra) = self.ultra {
+            options = options.ultra(ultra);
+        }
+        if let Some(long) = self.long {
+            options = options.long(long);
+        }
+        if let Some(window_log) = self.window_log {
+            options = options.window_log(window_log);
+        }
+        if let Some(strategy) = self.strategy {
+            options = options.strategy(strategy);
+        }
+        if self.seekable == Some(true) {
+            options = options.seekable(self.frame_size.unwrap_or(FRAME_SIZE_DEFAULT));
+        }
+        options = self
+            .include
+            .into_iter()
+            .fold(options, BatchOptions::include);
+        self.exclude
+            .into_iter()
+            .fold(options, BatchOptions::exclude)
+    }
+}
+
+/// User config file, `$XDG_CONFIG_HOME/zst_compress/config.toml`
+///
+/// Without `XDG_CONFIG_HOME` it is looked for in `~/.config`, or on Windows
+/// in `%APPDATA%`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("zst_compress").join("config.toml"))
+}
+
+/// One parsed config file
+struct ConfigFile {
+    path: PathBuf,
+    defaults: Settings,
+    profiles: toml::Table,
+}
+
+impl ConfigFile {
+    /// Read the config file at `path`, `None` if there is none
+    fn read(path: &Path) -> Result<Option<Self>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::io(Operation::ReadConfig, path, e)),
+        };
+        let mut table: toml::Table = text.parse().map_err(|e| invalid(path, e))?;
+        let profiles = match table.remove("profile") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => return Err(invalid(path, "profile must be a table of profiles")),
+            None => toml::Table::new(),
+        };
+        let mut file = Self {
+            path: path.to_path_buf(),
+            defaults: Settings::default(),
+            profiles,
+        };
+        file.defaults = file.settings(toml::Value::Table(table), None)?;
+        Ok(Some(file))
+    }
+
+    /// Settings of `table`, with relative paths taken from the directory of the file
+    fn settings(&self, table: toml::Value, profile: Option<&str>) -> Result<Settings> {
+        let mut settings: Settings = table.try_into().map_err(|e| match profile {
+            Some(profile) => invalid(&self.path, format!("profile {profile}: {e}")),
+            None => invalid(&self.path, e),
+        })?;
+        // The run changes into the processed directory, so the target is made absolute
+        if let Some(target) = &settings.target {
+            let file = fs::canonicalize(&self.path)
+                .map_err(|e| Error::io(Operation::ReadConfig, &self.path, e))?;
+            if let Some(dir) = file.parent() {
+                settings.target = Some(dir.join(target));
+            }
+        }
+        Ok(settings)
+    }
+}
+
+/// Settings of the `ZST_COMPRESS_<KEY>` environment variables, those of
+/// other keys are left to whatever else uses the prefix
+fn from_env() -> Result<Settings> {
+    let mut table = toml::Table::new();
+    for (name, value) in env::vars() {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        if name == ENV_PROFILE {
+            continue;
+        }
+        let key = key.to_lowercase().replace('_', "-");
+        if !KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        let value = if LIST_KEYS.contains(&key.as_str()) {
+            toml::Value::Array(
+                value
+                    .split(',')
+                    .filter(|pattern| !pattern.is_empty())
+                    .map(|pattern| toml::Value::String(pattern.to_string()))
+                    .collect(),
+            )
+        } else if let Ok(number) = value.parse() {
+            toml::Value::Integer(number)
+        } else if let Ok(switch) = value.parse() {
+            toml::Value::Boolean(switch)
+        } else {
+            toml::Value::String(value)
+        };
+        table.insert(key, value);
+    }
+    toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| Error::InvalidOption(format!("{ENV_PREFIX}* variables: {e}")))
+}
+
+fn invalid(path: &Path, message: impl ToString) -> Error {
+    Error::io(
+        Operation::ReadConfig,
+        path,
+        io::Error::new(io::ErrorKind::InvalidData, message.to_string().trim_end()),
+    )
+}
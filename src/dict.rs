@@ -0,0 +1,84 @@
+use crate::auxiliary::AtomicFile;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use zstd::zstd_safe;
+
+/// Prefix of dictionary sidecars, `zst_` keeps them out of the batch items
+pub static S_DICT: &str = "zst_dict-";
+
+/// Each sample is the start of the tar stream of one item
+const SAMPLE_SIZE: usize = 128 * 1024;
+const SAMPLES_MAX: usize = 2000;
+
+/// File name of the sidecar holding the dictionary `id`
+pub fn dict_file_name(id: u32) -> String {
+    format!("{S_DICT}{id}.dict")
+}
+
+/// ID a dictionary is known by in the zstd frames made with it
+pub fn dict_id(dict: &[u8]) -> Option<u32> {
+    zstd_safe::get_dict_id_from_dict(dict).map(|id| id.get())
+}
+
+/// Train a dictionary of at most `max_size` bytes from samples of `items`
+///
+/// Items are sampled evenly across the batch. Each sample is the start of
+/// the tar stream the item would be archived as, headers included.
+pub fn train(items: &[PathBuf], max_size: usize) -> io::Result<Vec<u8>> {
+    let step = items.len().div_ceil(SAMPLES_MAX).max(1);
+    let mut samples = vec![];
+    for f_path in items.iter().step_by(step) {
+        let Some(f_name) = f_path.file_name() else {
+            continue;
+        };
+        let mut sample = Sample(Vec::with_capacity(SAMPLE_SIZE));
+        {
+            let mut builder = tar::Builder::new(&mut sample);
+            let appended = match f_path.is_dir() {
+                true => builder.append_dir_all(f_name, f_path),
+                false => builder.append_path_with_name(f_path, f_name),
+            };
+            // A full sample stops the builder, anything else is a real error
+            if let Err(e) = appended
+                && e.kind() != io::ErrorKind::WriteZero
+            {
+                return Err(e);
+            }
+        }
+        samples.push(sample.0);
+    }
+    zstd::dict::from_samples(&samples, max_size)
+}
+
+/// Keep a copy of `dict` as a sidecar in `dir`, returning its path
+pub fn save(dict: &[u8], dir: &Path) -> io::Result<PathBuf> {
+    let id = dict_id(dict)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "not a zstd dictionary"))?;
+    let path = dir.join(dict_file_name(id));
+    if fs::read(&path).is_ok_and(|saved| saved == dict) {
+        return Ok(path);
+    }
+    let mut file = AtomicFile::create(&path)?;
+    file.write_all(dict)?;
+    file.commit()?;
+    Ok(path)
+}
+
+/// Buffer refusing to grow past [`SAMPLE_SIZE`]
+struct Sample(Vec<u8>);
+
+impl Write for Sample {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(SAMPLE_SIZE - self.0.len());
+        if n == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        self.0.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
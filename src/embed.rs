@@ -0,0 +1,104 @@
+use crate::manifest::ManifestEntry;
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Magic of the skippable frame holding the embedded data, zstd decoders pass over it
+const EMBED_MAGIC: u32 = 0x184D_2A5B;
+/// First bytes of the frame content, after the magic and the length
+const EMBED_TAG: &[u8; 4] = b"zstm";
+
+/// Listing, manifest and metadata carried by the archive itself
+///
+/// They are kept in a skippable frame at the start of the archive, so they
+/// travel with the `.tar.zst` wherever it is copied, while the archive stays
+/// readable by any zstd decoder. [`Embedded::read`] gets them back without
+/// decoding anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Embedded {
+    /// Key/value pairs: `created`, `creator`, `host`, `tool`, `level`,
+    /// `source`, `note` and any given with `--meta`
+    pub metadata: BTreeMap<String, String>,
+    /// The `_archived-filelist.txt` tree of a directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listing: Option<String>,
+    /// Every entry at full depth, see [`manifest`](crate::manifest)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub manifest: Vec<ManifestEntry>,
+}
+
+impl Embedded {
+    /// Read the embedded data of `archive_path`, `None` for an archive without any
+    pub fn read(archive_path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(archive_path)?;
+        let mut head = [0; 12];
+        match file.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        if head[..4] != EMBED_MAGIC.to_le_bytes() || &head[8..] != EMBED_TAG {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(head[4..8].try_into().unwrap()) as usize;
+        let mut payload = vec![0; len.saturating_sub(EMBED_TAG.len())];
+        file.read_exact(&mut payload)?;
+        serde_json::from_slice(&payload).map(Some).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("embedded data is unreadable: {e}"),
+            )
+        })
+    }
+
+    /// Write the data as a skippable frame to `writer`, ahead of the zstd frames
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let payload = serde_json::to_vec(self).map_err(io::Error::other)?;
+        let len = u32::try_from(payload.len() + EMBED_TAG.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "embedded data too large for a skippable frame",
+            )
+        })?;
+        writer.write_all(&EMBED_MAGIC.to_le_bytes())?;
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(EMBED_TAG)?;
+        writer.write_all(&payload)
+    }
+}
+
+/// Metadata describing the archive of `source` made at `level`
+pub fn metadata(source: &Path, level: i32) -> BTreeMap<String, String> {
+    let mut metadata = BTreeMap::new();
+    metadata.insert(
+        "created".to_string(),
+        Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    );
+    if let Ok(creator) = env::var("USER").or_else(|_| env::var("USERNAME")) {
+        metadata.insert("creator".to_string(), creator);
+    }
+    if let Some(host) = host_name() {
+        metadata.insert("host".to_string(), host);
+    }
+    metadata.insert(
+        "tool".to_string(),
+        concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).to_string(),
+    );
+    metadata.insert("level".to_string(), level.to_string());
+    let source = source.canonicalize().unwrap_or(source.to_path_buf());
+    metadata.insert("source".to_string(), source.to_string_lossy().into_owned());
+    metadata
+}
+
+fn host_name() -> Option<String> {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+}
@@ -0,0 +1,174 @@
+use crate::preflight::Problem;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Exit codes of the CLI, kept stable for scripts
+pub static RET_TAR_ERROR: u8 = 1;
+pub static RET_ITEM_ERROR: u8 = 2;
+pub static RET_DIR_ERROR: u8 = 3;
+
+/// Result type used throughout the library
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The operation that was running when an error happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    /// Change into the directory to start processing
+    ChangeDir,
+    /// Read the entries of a directory
+    ReadDir,
+    /// Write tar entries and zstd stream into an archive
+    Compress,
+    /// Decode and unpack an archive
+    Extract,
+    /// Read back an archive and compare it against its source
+    Verify,
+    /// Read the entries of an archive
+    List,
+    /// Read the frame header and index of an archive
+    ReadInfo,
+    /// Write the `*_archived-filelist.txt` listing
+    WriteListing,
+    /// Write the `*_archived-manifest.*` file
+    WriteManifest,
+    /// Write the `*_archived-message.txt` file
+    WriteMessage,
+    /// Remove an original after processing
+    Remove,
+    /// Open the run report
+    WriteReport,
+    /// Read a `zst_compress.toml` or user config file
+    ReadConfig,
+    /// Read an `--include-from` / `--exclude-from` pattern list
+    ReadPatterns,
+    /// Read the journal of an earlier run
+    ReadJournal,
+    /// Record progress in the journal
+    WriteJournal,
+    /// Load, train or save the zstd dictionary of a batch
+    Dictionary,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Operation::ChangeDir => "change into directory",
+            Operation::ReadDir => "read directory",
+            Operation::Compress => "compress",
+            Operation::Extract => "extract",
+            Operation::Verify => "verify",
+            Operation::List => "list",
+            Operation::ReadInfo => "read archive info",
+            Operation::WriteListing => "write directory listing",
+            Operation::WriteManifest => "write manifest",
+            Operation::WriteMessage => "write message file",
+            Operation::Remove => "remove",
+            Operation::WriteReport => "write report",
+            Operation::ReadConfig => "read config file",
+            Operation::ReadPatterns => "read pattern file",
+            Operation::ReadJournal => "read journal",
+            Operation::WriteJournal => "write journal",
+            Operation::Dictionary => "prepare dictionary",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Errors reported by the library
+#[derive(Debug)]
+pub enum Error {
+    /// An I/O error while running `op` on `path`
+    Io {
+        op: Operation,
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// The path has no file name usable as an archive name
+    InvalidName(PathBuf),
+    /// A glob or regex filter does not compile
+    InvalidPattern { pattern: String, message: String },
+    /// An option value the linked libraries do not support
+    InvalidOption(String),
+    /// Some items of a batch failed, each was reported when it happened
+    Batch { failed: usize, total: usize },
+    /// The checks before a run found it cannot finish, nothing was changed
+    Preflight(Vec<Problem>),
+}
+
+impl Error {
+    /// Wrap an [`io::Error`] with the operation and path it belongs to
+    pub fn io(op: Operation, path: &Path, source: io::Error) -> Self {
+        Error::Io {
+            op,
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// The operation that failed, if the error comes from one
+    pub fn operation(&self) -> Option<Operation> {
+        match self {
+            Error::Io { op, .. } => Some(*op),
+            _ => None,
+        }
+    }
+
+    /// Kind of the underlying I/O error, e.g. to tell permission denied from disk full
+    pub fn io_kind(&self) -> Option<io::ErrorKind> {
+        match self {
+            Error::Io { source, .. } => Some(source.kind()),
+            _ => None,
+        }
+    }
+
+    /// Exit code of the CLI for this error
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Io {
+                op: Operation::Compress | Operation::Extract | Operation::Verify | Operation::List,
+                ..
+            } => RET_TAR_ERROR,
+            Error::Io {
+                op: Operation::ChangeDir | Operation::ReadDir,
+                ..
+            }
+            | Error::Preflight(_) => RET_DIR_ERROR,
+            Error::Io { .. }
+            | Error::InvalidName(_)
+            | Error::InvalidPattern { .. }
+            | Error::InvalidOption(_)
+            | Error::Batch { .. } => RET_ITEM_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { op, path, source } => write!(f, "failed to {op} {path:?}: {source}"),
+            Error::InvalidName(path) => write!(f, "no valid file name in {path:?}"),
+            Error::InvalidPattern { pattern, message } => {
+                write!(f, "invalid pattern {pattern:?}: {message}")
+            }
+            Error::InvalidOption(message) => write!(f, "invalid option: {message}"),
+            Error::Batch { failed, total } => write!(f, "{failed} of {total} items failed"),
+            Error::Preflight(problems) => {
+                f.write_str("nothing was changed")?;
+                for problem in problems {
+                    write!(f, "; {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use crate::archiver::{Action, BatchSummary, ItemOutcome};
+use crate::error::Error;
+use crate::exec::human_size;
+use crate::list::{self, ListStyle};
+use std::io::{Write, stdout};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// How much the console prints
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// Errors only
+    Quiet,
+    /// One line per item
+    #[default]
+    Normal,
+    /// Also tar entries, removals, timings, ratios and thread counts
+    Verbose,
+}
+
+/// Something that happened during a run
+///
+/// `current` counts the items in the order they were collected, `total` is
+/// the number of items of the run.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Items were collected, `zstd_workers` is the zstd thread count of each item
+    BatchStart {
+        total: usize,
+        jobs: usize,
+        zstd_workers: u32,
+    },
+    /// Work on an item begins
+    ItemStart {
+        current: usize,
+        total: usize,
+        action: Action,
+        path: &'a Path,
+    },
+    /// A tar entry of `item` was packed or unpacked
+    Entry { item: &'a Path, entry: &'a Path },
+    /// A file was removed after processing
+    Removed { path: &'a Path },
+    /// An item is done
+    ItemDone {
+        current: usize,
+        total: usize,
+        outcome: &'a ItemOutcome,
+    },
+    /// An item failed, the batch goes on with the next one
+    ItemFailed {
+        current: usize,
+        total: usize,
+        action: Action,
+        path: &'a Path,
+        error: &'a Error,
+        /// Time spent on the item until it failed
+        duration: Duration,
+    },
+    /// A note that is not about one item
+    Info(&'a str),
+    /// A problem that does not fail the item or the batch
+    Error(&'a str),
+    /// All items are done
+    BatchDone {
+        summary: &'a BatchSummary,
+        duration: Duration,
+    },
+}
+
+/// Receiver of the events of a run
+///
+/// Set one with [`Archiver::reporter`](crate::archiver::Archiver::reporter)
+/// to get the events instead of the console output. Items processed in
+/// parallel call it from several threads.
+pub trait Reporter: Send + Sync {
+    fn event(&self, event: &Event<'_>);
+}
+
+/// Several reporters receiving the same events
+pub(crate) struct Fanout<'a>(pub(crate) Vec<&'a dyn Reporter>);
+
+impl Reporter for Fanout<'_> {
+    fn event(&self, event: &Event<'_>) {
+        for reporter in &self.0 {
+            reporter.event(event);
+        }
+    }
+}
+
+/// Progress lines on stdout and errors on stderr
+///
+/// Items run one by one print their line piece by piece. Items run in
+/// parallel print one whole line each when they finish, counted in order of
+/// completion, so lines of different items never interleave.
+pub struct ConsoleReporter {
+    verbosity: Verbosity,
+    inline: bool,
+    list_style: ListStyle,
+    finished: AtomicUsize,
+}
+
+impl ConsoleReporter {
+    /// `inline` prints each item as it goes, for items run one by one
+    pub fn new(verbosity: Verbosity, inline: bool) -> Self {
+        Self {
+            verbosity,
+            inline,
+            list_style: ListStyle::default(),
+            finished: AtomicUsize::new(0),
+        }
+    }
+
+    /// Print the entries of listed archives in `list_style`
+    pub fn list_style(mut self, list_style: ListStyle) -> Self {
+        self.list_style = list_style;
+        self
+    }
+
+    fn verbose(&self) -> bool {
+        self.verbosity == Verbosity::Verbose
+    }
+
+    /// Print the end of an item line, the whole line for parallel items
+    fn finish_line(&self, total: usize, action: Action, path: &Path, tail: &str) {
+        let finished = self.finished.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.verbosity == Verbosity::Quiet {
+            return;
+        }
+        if self.inline && self.verbose() {
+            if !tail.is_empty() {
+                println!("    {}", tail.trim_start());
+            }
+        } else if self.inline {
+            println!("{tail}");
+        } else {
+            println!("({finished}/{total}) {}{tail}", head(action, path));
+        }
+    }
+}
+
+impl Reporter for ConsoleReporter {
+    fn event(&self, event: &Event<'_>) {
+        let quiet = self.verbosity == Verbosity::Quiet;
+        match *event {
+            Event::BatchStart {
+                total,
+                jobs,
+                zstd_workers,
+            } if self.verbose() => {
+                println!("{total} items, {jobs} at a time with {zstd_workers} zstd threads each");
+            }
+            Event::ItemStart {
+                current,
+                total,
+                action,
+                path,
+            } if self.inline && !quiet => {
+                print!("({current}/{total}) {}", head(action, path));
+                if self.verbose() {
+                    println!();
+                }
+                let _ = stdout().flush();
+            }
+            Event::Entry { item, entry } if self.verbose() => match self.inline {
+                true => println!("    {}", entry.display()),
+                false => println!("    {}: {}", item.display(), entry.display()),
+            },
+            Event::Removed { path } if self.verbose() => {
+                println!("    Removed {:?}", path)
+            }
+            Event::ItemDone { total, outcome, .. } => {
+                let mut tail = match (outcome.action, &outcome.destination, outcome.entries) {
+                    (Action::Verify, _, Some(entries)) => format!(" -> OK, {entries} entries"),
+                    (Action::List, _, Some(entries)) => format!(" -> {entries} entries"),
+                    (Action::Compress | Action::Extract, Some(destination), _) => {
+                        format!(" -> {:?}", destination)
+                    }
+                    _ => String::new(),
+                };
+                if self.verbose() && outcome.action != Action::Skip {
+                    if let (Some(original), Some(archive)) =
+                        (outcome.original_bytes, outcome.archive_bytes)
+                    {
+                        tail.push_str(&format!(
+                            ", {} -> {}",
+                            human_size(original),
+                            human_size(archive)
+                        ));
+                        if original > 0 {
+                            let ratio = archive as f64 / original as f64 * 100.0;
+                            tail.push_str(&format!(" ({ratio:.1}%)"));
+                        }
+                    }
+                    tail.push_str(&format!(" in {:.2}s", outcome.duration.as_secs_f64()));
+                }
+                // The listing and the decisions stay right below the line of their item
+                let mut out = stdout().lock();
+                self.finish_line(total, outcome.action, &outcome.source, &tail);
+                if !quiet {
+                    for decision in &outcome.decisions {
+                        let _ = writeln!(out, "    {decision}");
+                    }
+                }
+                if let Some(listing) = &outcome.listing
+                    && !quiet
+                {
+                    let _ = write!(out, "{}", list::render(listing, self.list_style));
+                }
+            }
+            Event::ItemFailed {
+                total,
+                action,
+                path,
+                error,
+                ..
+            } => {
+                let tail = match action {
+                    Action::Verify => " -> CORRUPT",
+                    _ => " -> FAILED",
+                };
+                self.finish_line(total, action, path, tail);
+                eprintln!("出错了! {error}");
+            }
+            Event::Info(text) if !quiet => println!("{text}"),
+            Event::Error(text) => eprintln!("出错了! {text}"),
+            Event::BatchDone { summary, duration } if self.verbose() => {
+                let original: u64 = summary
+                    .outcomes
+                    .iter()
+                    .filter_map(|o| o.original_bytes)
+                    .sum();
+                let archive: u64 = summary
+                    .outcomes
+                    .iter()
+                    .filter_map(|o| o.archive_bytes)
+                    .sum();
+                println!(
+                    "Done: {} items, {} failed, {} -> {} in {:.2}s",
+                    summary.total(),
+                    summary.failed(),
+                    human_size(original),
+                    human_size(archive),
+                    duration.as_secs_f64()
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Start of an item line, e.g. `Compress: "dir"`
+fn head(action: Action, path: &Path) -> String {
+    let verb = match action {
+        Action::Compress => "Compress",
+        Action::Extract => "Extract",
+        Action::Verify => "Verify",
+        Action::List => "List",
+        Action::Skip => "Skip",
+    };
+    format!("{verb}: {:?}", path)
+}
@@ -1,230 +1,1095 @@
-use std::cmp::max;
-use std::fs::{File, remove_dir_all, remove_file};
-use std::io::{copy, prelude::*, stdout};
-use std::path::Path;
+use crate::archiver::{Action, BatchOptions, ItemOutcome, Mode};
+use crate::attrs::{self, Attrs, Names};
+use crate::auxiliary::{AtomicFile, CountingWriter};
+use crate::embed::{self, Embedded};
+use crate::error::{Error, Operation, Result};
+use crate::events::{Event, Reporter};
+use crate::filter::Members;
+use crate::frame::{self, open_decoder, open_decoder_at};
+use crate::journal::{ItemState, Journal};
+use crate::list;
+use crate::manifest::{self, ManifestEntry, ManifestFormat};
+use crate::params::ZstdParams;
+use crate::policy::{self, Decision, EntryDecision, EntryPolicy, HardlinkPolicy, SymlinkPolicy};
+use crate::safety::{self, ConflictPolicy, ExistingPolicy};
+use crate::seekable::{self, EntryInfo, Index};
+use crate::verify::{self, verify_archive};
+use clap::ValueEnum;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, create_dir_all, remove_dir_all, remove_file};
+use std::io::{self, copy, prelude::*};
+use std::path::{Path, PathBuf};
 use std::thread;
+use std::time::{Duration, Instant};
 
 // Set the skipped / selected patterns
-static S_ARCHIVE: &str = ".tar.zst";
+pub(crate) static S_ARCHIVE: &str = ".tar.zst";
 static S_ARCHILIST: &str = "_archived-filelist.txt";
 static S_FLAG_MESSAGE: &str = "_archived-message.txt";
+static S_MANIFEST: &str = "_archived-manifest.";
 static S_TOOL: &str = "zst_";
-static RET_TAR_ERROR: u8 = 1;
-pub static RET_ITEM_ERROR: u8 = 2;
-pub static RET_DIR_ERROR: u8 = 3;
 
-/// Compress or decompress 1 item
-#[allow(clippy::too_many_arguments)]
-pub fn entry_archive(
+/// Whether `f_name` is one of the tool's own files, never processed
+pub(crate) fn is_tool_name(f_name: &str) -> bool {
+    f_name.starts_with(S_TOOL)
+}
+
+/// Whether `f_name` is a `*_archived-manifest.<ext>` file
+fn is_manifest_name(f_name: &str) -> bool {
+    ManifestFormat::value_variants()
+        .iter()
+        .any(|format| f_name.ends_with(&manifest_name("", *format)))
+}
+
+/// Name of the manifest of the item `f_name`
+fn manifest_name(f_name: &str, format: ManifestFormat) -> String {
+    format!("{f_name}{S_MANIFEST}{}", format.extension())
+}
+
+/// Whether `f_name` is a `.tar.zst` archive
+pub(crate) fn is_archive_name(f_name: &str) -> bool {
+    f_name.ends_with(S_ARCHIVE)
+}
+
+pub(crate) use dir_listing::{dir_size, human_size, system_time_to_date_time};
+
+/// Compress or decompress 1 item, telling `events` what happens
+///
+/// With a `journal`, each step is recorded once complete, and an item the
+/// journal already has is picked up after its last complete step.
+pub(crate) fn entry_archive(
     f_path: &Path,
-    compress: bool,
-    preserve: bool,
-    flag: bool,
-    target_dir: &Path,
-    level_tree: u8,
-    level_zstd: i32,
+    options: &BatchOptions,
     current: usize,
     total: usize,
-    dry_run: bool,
-) -> Result<(), u8> {
-    let mut ret = 0;
+    events: &dyn Reporter,
+    journal: Option<&Journal>,
+) -> Result<ItemOutcome> {
+    let started = Instant::now();
+    let resumed = journal.and_then(|journal| journal.previous(f_path));
+    let mut outcome = ItemOutcome {
+        action: match resumed {
+            Some(ItemState::Done) => Action::Skip,
+            _ => action_of(f_path, options.mode),
+        },
+        source: f_path.to_path_buf(),
+        destination: None,
+        original_bytes: None,
+        archive_bytes: None,
+        entries: None,
+        listing: None,
+        decisions: vec![],
+        duration: Duration::ZERO,
+    };
+    events.event(&Event::ItemStart {
+        current,
+        total,
+        action: outcome.action,
+        path: f_path,
+    });
 
-    // Get clean name and determined target_dir
+    let steps = Steps {
+        f_path,
+        journal,
+        resumed,
+    };
+    match process_entry(f_path, options, &mut outcome, events, &steps) {
+        Ok(()) => {
+            outcome.duration = started.elapsed();
+            events.event(&Event::ItemDone {
+                current,
+                total,
+                outcome: &outcome,
+            });
+            Ok(outcome)
+        }
+        Err(error) => {
+            events.event(&Event::ItemFailed {
+                current,
+                total,
+                action: outcome.action,
+                path: f_path,
+                error: &error,
+                duration: started.elapsed(),
+            });
+            Err(error)
+        }
+    }
+}
+
+/// What `mode` does with the item at `f_path`
+pub(crate) fn action_of(f_path: &Path, mode: Mode) -> Action {
     let f_name = f_path
         .file_name()
-        .ok_or(RET_ITEM_ERROR)?
-        .to_str()
-        .ok_or(RET_ITEM_ERROR)?;
-
-    // Print progress counting
-    print!("({current}/{total}) ");
-
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
     // Skip filelists and tools
-    if f_name.find(S_TOOL) == Some(0)
-        || (f_name.len() >= S_ARCHILIST.len()
-            && f_name.rfind(S_ARCHILIST) == Some(f_name.len() - S_ARCHILIST.len()))
-        || (f_name.len() >= S_FLAG_MESSAGE.len()
-            && f_name.rfind(S_FLAG_MESSAGE) == Some(f_name.len() - S_FLAG_MESSAGE.len()))
+    if is_tool_name(&f_name)
+        || f_name.ends_with(S_ARCHILIST)
+        || f_name.ends_with(S_FLAG_MESSAGE)
+        || is_manifest_name(&f_name)
     {
-        println!("Skip: {:?}", f_path);
+        Action::Skip
     }
     // Selected archive files
-    else if f_name.len() >= S_ARCHIVE.len()
-        && f_name.rfind(S_ARCHIVE) == Some(f_name.len() - S_ARCHIVE.len())
-    {
+    else if is_archive_name(&f_name) {
+        match mode {
+            Mode::Extract => Action::Extract,
+            Mode::Verify => Action::Verify,
+            Mode::List => Action::List,
+            Mode::Compress => Action::Skip,
+        }
+    } else {
+        match mode {
+            Mode::Compress => Action::Compress,
+            Mode::Extract | Mode::Verify | Mode::List => Action::Skip,
+        }
+    }
+}
+
+/// Steps of one item already done in an earlier run, and the journal of this one
+struct Steps<'a> {
+    f_path: &'a Path,
+    journal: Option<&'a Journal>,
+    resumed: Option<ItemState>,
+}
+
+impl Steps<'_> {
+    /// Whether `state` was reached before this run
+    fn done(&self, state: ItemState) -> bool {
+        self.resumed.is_some_and(|resumed| resumed >= state)
+    }
+
+    /// Whether the item was started but left before its first complete step
+    fn half_done(&self) -> bool {
+        self.resumed == Some(ItemState::Started)
+    }
+
+    /// Record that work on the item begins, unless an earlier run began it
+    fn start(&self) -> Result<()> {
+        match self.resumed {
+            None => self.record(ItemState::Started),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn record(&self, state: ItemState) -> Result<()> {
+        match self.journal {
+            Some(journal) => journal.record(self.f_path, state),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Do the action of `outcome` and fill in what it produced
+fn process_entry(
+    f_path: &Path,
+    options: &BatchOptions,
+    outcome: &mut ItemOutcome,
+    events: &dyn Reporter,
+    steps: &Steps,
+) -> Result<()> {
+    let preserve = options.preserve;
+    let dry_run = options.dry_run;
+    let target_dir_buf = options.item_target_dir(f_path);
+    let target_dir = target_dir_buf.as_path();
+    let members = Members::new(options)?;
+    let mut codec = Codec {
+        params: &options.zstd,
+        zstd_workers: options.zstd_workers(),
+        dictionary: &[],
+        frame_size: options.frame_size,
+        members: &members,
+        attrs: options.attrs,
+        policy: options.policy,
+        existing: options.existing,
+        merge: false,
+        embedded: None,
+        top: None,
+    };
+    let mut ret = Ok(());
+    let mut on_entry = |entry: &Path| {
+        events.event(&Event::Entry {
+            item: f_path,
+            entry,
+        })
+    };
+
+    // Get clean name and determined target_dir
+    let f_name = f_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::InvalidName(f_path.to_path_buf()))?;
+
+    match outcome.action {
+        Action::Skip => {}
         // Decompress and clean
-        if !compress {
-            print!("Extract: {:?}", f_path);
-            let _ = stdout().flush();
+        Action::Extract => {
             let f_ori_name = &f_name[0..f_name.rfind(S_ARCHIVE).unwrap()];
             let f_ori_buf = target_dir.join(f_ori_name);
             let f_ori = f_ori_buf.as_path();
-            if !dry_run && do_archive(f_path, target_dir, false, level_zstd).is_err() {
-                eprintln!("出错了! Failed to extract {:?}", f_path);
-                return Err(RET_TAR_ERROR);
+            outcome.archive_bytes = f_path.metadata().ok().map(|m| m.len());
+            // Unpacking again overwrites whatever a broken run left of its own
+            if !dry_run && !steps.done(ItemState::Archived) {
+                if steps.half_done() {
+                    codec.existing = ExistingPolicy::Overwrite;
+                }
+                let layout = safety::inspect(
+                    f_path,
+                    f_ori_name,
+                    target_dir,
+                    &members,
+                    options.tarbomb,
+                    codec.existing,
+                )
+                .map_err(|e| Error::io(Operation::Extract, f_path, e))?;
+                steps.start()?;
+                codec.top = layout.top;
+                if layout.redirected {
+                    outcome.decisions.push(EntryDecision {
+                        path: PathBuf::from(f_ori_name),
+                        decision: Decision::TarbombRedirected,
+                        link: None,
+                    });
+                }
+                let bytes = do_archive(
+                    f_path,
+                    &layout.target,
+                    false,
+                    &codec,
+                    &mut on_entry,
+                    &mut outcome.decisions,
+                )?;
+                outcome.original_bytes = Some(bytes);
+                steps.record(ItemState::Archived)?;
             }
-            println!(" -> {:?}", f_ori);
+            outcome.destination = Some(match options.strip_components {
+                0 => f_ori_buf.clone(),
+                _ => target_dir.to_path_buf(),
+            });
 
-            // Remove original file
-            if !preserve && !dry_run {
-                let _ = f_remove_print(f_path, false);
+            // Remove original file, unless only part of it was extracted
+            if !preserve && members.is_all() && !dry_run && !steps.done(ItemState::Removed) {
+                let _ = f_remove_print(f_path, false, events);
                 let f_list_buf = f_ori.with_file_name(format!("{f_ori_name}{S_ARCHILIST}"));
                 let f_list = f_list_buf.as_path();
                 if Path::exists(f_list) {
-                    let _ = f_remove_print(f_list, false);
+                    let _ = f_remove_print(f_list, false, events);
+                }
+                for format in ManifestFormat::value_variants() {
+                    let f_manifest = f_ori.with_file_name(manifest_name(f_ori_name, *format));
+                    if f_manifest.exists() {
+                        let _ = f_remove_print(&f_manifest, false, events);
+                    }
                 }
                 let f_id_buf = f_ori.with_file_name(format!("{f_ori_name}{S_FLAG_MESSAGE}"));
                 let f_id = f_id_buf.as_path();
                 if Path::exists(f_id) {
-                    let _ = f_remove_print(f_id, false);
+                    let _ = f_remove_print(f_id, false, events);
+                }
+                if !f_path.exists() {
+                    steps.record(ItemState::Removed)?;
                 }
             }
-        } else {
-            println!("Skip: {:?}", f_path);
         }
-    }
-    // Compress, mark the filelist and clean
-    else if compress {
-        // Make filelist
-        if f_path.is_dir() {
-            let f_list_path_buf = target_dir.join(format!("{f_name}{S_ARCHILIST}"));
-            let f_list_path = f_list_path_buf.as_path();
+        // Read back without changing anything
+        Action::Verify => {
+            if !dry_run {
+                outcome.entries = Some(verify_archive(f_path, None)?);
+            }
+        }
+        // Read the headers only, nothing is written even without dry run
+        Action::List => {
+            let entries =
+                list::read_entries(f_path).map_err(|e| Error::io(Operation::List, f_path, e))?;
+            outcome.archive_bytes = f_path.metadata().ok().map(|m| m.len());
+            outcome.original_bytes = Some(entries.iter().map(|e| e.size).sum());
+            outcome.entries = Some(entries.len());
+            outcome.listing = Some(entries);
+        }
+        // Compress, mark the filelist and clean
+        Action::Compress => {
+            // Settle what happens to an archive already there. A broken run
+            // leaves at most its temporary file, the archive is not its own
+            let mut f_out = target_dir.join(format!("{f_name}{S_ARCHIVE}"));
+            if !steps.done(ItemState::Archived) && f_out.exists() {
+                let archive_name = PathBuf::from(f_out.file_name().unwrap());
+                let (decision, link) = match options.conflict {
+                    ConflictPolicy::Fail if !dry_run => {
+                        return Err(Error::io(
+                            Operation::Compress,
+                            &f_out,
+                            io::Error::new(
+                                io::ErrorKind::AlreadyExists,
+                                "the archive exists already",
+                            ),
+                        ));
+                    }
+                    ConflictPolicy::Fail => (Decision::ArchiveFails, None),
+                    ConflictPolicy::Skip => (Decision::ArchiveSkipped, None),
+                    ConflictPolicy::Overwrite => (Decision::ArchiveOverwritten, None),
+                    ConflictPolicy::Versioned => {
+                        f_out = safety::versioned_name(&f_out, S_ARCHIVE);
+                        let link = PathBuf::from(f_out.file_name().unwrap());
+                        (Decision::ArchiveVersioned, Some(link))
+                    }
+                    ConflictPolicy::Merge => {
+                        codec.merge = true;
+                        (Decision::ArchiveMerged, None)
+                    }
+                };
+                outcome.decisions.push(EntryDecision {
+                    path: archive_name,
+                    decision,
+                    link,
+                });
+                if options.conflict == ConflictPolicy::Skip {
+                    if !dry_run {
+                        steps.record(ItemState::Done)?;
+                    }
+                    return Ok(());
+                }
+            }
+            // Files beside the archive are named after it
+            let f_stem = f_out.file_name().unwrap().to_string_lossy();
+            let f_stem = f_stem.strip_suffix(S_ARCHIVE).unwrap().to_string();
+
+            if !dry_run && !steps.done(ItemState::Archived) {
+                steps.start()?;
+            }
+
+            // Mirror the place of nested items below the target
+            if !dry_run && !target_dir.is_dir() {
+                create_dir_all(target_dir)
+                    .map_err(|e| Error::io(Operation::Compress, target_dir, e))?;
+            }
+
+            // Make filelist, unless the original may already be partly removed
+            if f_path.is_dir() && !steps.done(ItemState::Archived) {
+                let f_list_path_buf = target_dir.join(format!("{f_stem}{S_ARCHILIST}"));
+                let f_list_path = f_list_path_buf.as_path();
 
-            if let Err(e) = dir_listing::generate_listing(f_path, f_list_path, level_tree, dry_run)
+                if let Err(e) =
+                    dir_listing::generate_listing(f_path, f_list_path, options.level_tree, dry_run)
+                {
+                    events.event(&Event::Error(&format!(
+                        "Error generating directory listing for {}: {e}",
+                        f_path.display()
+                    )));
+                    ret = Err(Error::io(Operation::WriteListing, f_list_path, e));
+                }
+            }
+
+            // Hash everything at full depth, from the same state the archive is made of
+            let archiving = !dry_run && !steps.done(ItemState::Archived);
+            let mut manifest_entries = vec![];
+            if archiving && (options.manifest.is_some() || options.embed) {
+                manifest_entries = manifest::build(f_path, Path::new(f_name), &options.policy)
+                    .map_err(|e| Error::io(Operation::WriteManifest, f_path, e))?;
+                // Entries carried over keep what the old archive embeds of them
+                if codec.merge
+                    && let Some(old) = Embedded::read(&f_out)
+                        .map_err(|e| Error::io(Operation::WriteManifest, &f_out, e))?
+                {
+                    let packed: HashSet<_> =
+                        manifest_entries.iter().map(|e| e.path.clone()).collect();
+                    manifest_entries.extend(
+                        old.manifest
+                            .into_iter()
+                            .filter(|e| !packed.contains(&e.path)),
+                    );
+                }
+            }
+            if let Some(format) = options.manifest
+                && archiving
             {
-                eprintln!(
-                    "出错了! Error generating directory listing for {}: {e}",
-                    f_path.display()
-                );
-                ret = RET_ITEM_ERROR;
+                let f_manifest = target_dir.join(manifest_name(&f_stem, format));
+                manifest::write(&manifest_entries, &f_manifest, format)
+                    .map_err(|e| Error::io(Operation::WriteManifest, &f_manifest, e))?;
             }
-        }
 
-        // Compress
-        print!("Compress: {:?}", f_path);
-        let _ = stdout().flush();
-        let f_out = target_dir.join(format!("{f_name}{S_ARCHIVE}"));
-        if !dry_run && do_archive(f_path, target_dir, true, level_zstd).is_err() {
-            eprintln!("出错了! Failed to compress {:?}", f_path);
-            return Err(RET_TAR_ERROR);
-        }
-        println!(" -> {:?}", f_out);
+            // Compress
+            let mut old_entries = HashSet::new();
+            if archiving {
+                let dictionary = match &options.dictionary {
+                    Some(path) => {
+                        fs::read(path).map_err(|e| Error::io(Operation::Dictionary, path, e))?
+                    }
+                    None => vec![],
+                };
+                codec.dictionary = &dictionary;
+                let embedded = match options.embed {
+                    true => Some(embedded_data(f_path, options, manifest_entries)?),
+                    false => None,
+                };
+                codec.embedded = embedded.as_ref();
+                if codec.merge {
+                    old_entries = list::read_entries(&f_out)
+                        .map_err(|e| Error::io(Operation::Compress, &f_out, e))?
+                        .into_iter()
+                        .map(|e| e.path)
+                        .collect();
+                }
+                let bytes = do_archive(
+                    f_path,
+                    &f_out,
+                    true,
+                    &codec,
+                    &mut on_entry,
+                    &mut outcome.decisions,
+                )?;
+                outcome.original_bytes = Some(bytes);
+                steps.record(ItemState::Archived)?;
+            }
+            if !dry_run {
+                outcome.archive_bytes = f_out.metadata().ok().map(|m| m.len());
+            }
+            outcome.destination = Some(f_out.clone());
 
-        // Write the indicator text message
-        if flag && !dry_run {
-            let f_name_id_buf = f_path.with_file_name(format!("{f_name}{S_FLAG_MESSAGE}"));
-            let f_name_id = f_name_id_buf.as_path();
-            let mut f_id = File::create(f_name_id)
-                .unwrap_or_else(|_| panic!("出错了! Failed to create file: {:?}", f_name_id));
-            let message = format!(
-                "- 这是一则数据整理的消息
+            // Read back before anything is removed, the original is kept on failure
+            if options.verify && !dry_run && !steps.done(ItemState::Verified) {
+                outcome.entries = Some(verify::verify_merged(&f_out, f_path, &old_entries)?);
+                steps.record(ItemState::Verified)?;
+            }
+
+            // Write the indicator text message
+            if options.flag && !dry_run && !steps.done(ItemState::Removed) {
+                let f_name_id_buf = f_path.with_file_name(format!("{f_name}{S_FLAG_MESSAGE}"));
+                let f_name_id = f_name_id_buf.as_path();
+                let message = format!(
+                    "- 这是一则数据整理的消息
 
     - 原数据已经压缩，可能移动到新位置: 
       {:?}
     ",
-                f_out
-            );
-            f_id.write_all(message.as_bytes())
-                .unwrap_or_else(|_| panic!("出错了! Failed to write into file: {:?}", f_name_id));
-        }
+                    f_out
+                );
+                AtomicFile::create(f_name_id)
+                    .and_then(|mut f| {
+                        f.write_all(message.as_bytes())?;
+                        f.commit()
+                    })
+                    .map_err(|e| Error::io(Operation::WriteMessage, f_name_id, e))?;
+            }
 
-        // Remove original file
-        if !dry_run {
-            assert!(f_path.exists());
-            assert!(f_out.is_file());
-            if !preserve {
-                let _ = f_remove_print(f_path, f_path.is_dir());
+            // Remove original file
+            if !dry_run && !steps.done(ItemState::Removed) {
+                // Something else may have moved either away meanwhile
+                if let Err(e) = fs::symlink_metadata(f_path) {
+                    return Err(Error::io(Operation::Remove, f_path, e));
+                }
+                if !f_out.is_file() {
+                    return Err(Error::io(
+                        Operation::Remove,
+                        f_path,
+                        io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("the archive {f_out:?} is gone, the original is kept"),
+                        ),
+                    ));
+                }
+                if !preserve && f_remove_print(f_path, f_path.is_dir(), events).is_ok() {
+                    steps.record(ItemState::Removed)?;
+                }
             }
         }
-    } else {
-        println!("Skip: {:?}", f_path);
     }
 
-    match ret {
-        0 => Ok(()),
-        ret => Err(ret),
+    if ret.is_ok() && !options.dry_run && outcome.action != Action::Skip {
+        steps.record(ItemState::Done)?;
     }
+    ret
+}
+
+/// Listing, manifest and metadata put inside the archive of `f_path`
+fn embedded_data(
+    f_path: &Path,
+    options: &BatchOptions,
+    manifest: Vec<ManifestEntry>,
+) -> Result<Embedded> {
+    let listing = match f_path.is_dir() {
+        true => Some(
+            dir_listing::render_listing(f_path, options.level_tree)
+                .map_err(|e| Error::io(Operation::WriteListing, f_path, e))?,
+        ),
+        false => None,
+    };
+    let mut metadata = embed::metadata(f_path, options.zstd.level);
+    metadata.extend(options.metadata.iter().cloned());
+    Ok(Embedded {
+        metadata,
+        listing,
+        manifest,
+    })
+}
+
+/// How one item is packed and unpacked
+struct Codec<'a> {
+    /// Encoder settings
+    params: &'a ZstdParams,
+    /// Number of zstd threads of this item, compressing or decoding frames
+    zstd_workers: u32,
+    /// Dictionary to compress with, empty for none
+    dictionary: &'a [u8],
+    /// Write independent frames of this size and an entry index
+    frame_size: Option<usize>,
+    /// Entries to unpack
+    members: &'a Members,
+    /// Attributes to capture and restore
+    attrs: Attrs,
+    /// How symlinks, hard links, sparse and special files are archived
+    policy: EntryPolicy,
+    /// What unpacking does with files already there
+    existing: ExistingPolicy,
+    /// Carry over the entries of the archive already there that the item has none of
+    merge: bool,
+    /// Data to put ahead of the zstd frames
+    embedded: Option<&'a Embedded>,
+    /// Name every unpacked entry must be below, see [`safety::Layout::top`]
+    top: Option<OsString>,
 }
 
 /// Implement compression with archive library tar and zstd
-fn do_archive(f_path: &Path, target: &Path, compress: bool, level_zstd: i32) -> Result<(), u8> {
+///
+/// `target` is the archive to write on compression, and the directory to
+/// unpack into on extraction. `on_entry` gets the name of each tar entry
+/// packed or unpacked, and what the entry policy decided on compression
+/// goes to `decisions`.
+/// Returns the size of the file contents that were packed or unpacked.
+fn do_archive(
+    f_path: &Path,
+    target: &Path,
+    compress: bool,
+    codec: &Codec,
+    on_entry: &mut dyn FnMut(&Path),
+    decisions: &mut Vec<EntryDecision>,
+) -> Result<u64> {
     if compress {
         // Compression path: tar -> zstd
-        let output_path = target.join(format!(
-            "{}.tar.zst",
-            f_path.file_name().unwrap().to_str().unwrap()
-        ));
-        let output_file = File::create(&output_path).map_err(|_| RET_TAR_ERROR)?;
+        let err = |e| Error::io(Operation::Compress, f_path, e);
+        let f_name = f_path
+            .file_name()
+            .ok_or_else(|| Error::InvalidName(f_path.to_path_buf()))?;
+        let output_path = target;
+        let original_bytes = match f_path.is_dir() {
+            true => dir_listing::dir_size(f_path),
+            false => f_path.metadata().map(|m| m.len()),
+        }
+        .map_err(err)?;
+        let output_file = AtomicFile::create(output_path).map_err(err)?;
+        let embedded = codec.embedded.cloned();
+        let dictionary = codec.dictionary.to_vec();
+        let params = *codec.params;
+        let (zstd_workers, frame_size) = (codec.zstd_workers, codec.frame_size);
 
         let (mut reader, writer) = pipe::pipe();
 
         // 启动压缩线程
-        let compressor = thread::spawn(move || {
-            let mut encoder = zstd::stream::Encoder::new(output_file, level_zstd).unwrap();
-            let cpus = thread::available_parallelism().unwrap().get();
-            encoder.multithread(max(cpus as u32 / 2, 10)).unwrap();
-            copy(&mut reader, &mut encoder).unwrap();
-            encoder.finish().unwrap();
+        let compressor = thread::spawn(move || -> io::Result<(AtomicFile, Vec<_>)> {
+            let mut output = CountingWriter::new(output_file);
+            if let Some(embedded) = embedded {
+                embedded.write_to(&mut output)?;
+            }
+            if let Some(frame_size) = frame_size {
+                return seekable::compress_frames(
+                    &mut reader,
+                    output,
+                    frame_size,
+                    &params,
+                    zstd_workers,
+                    &dictionary,
+                );
+            }
+            let mut encoder =
+                zstd::stream::Encoder::with_dictionary(output, params.level, &dictionary)?;
+            params.apply(&mut encoder)?;
+            encoder.multithread(zstd_workers)?;
+            copy(&mut reader, &mut encoder)?;
+            Ok((encoder.finish()?.into_inner(), vec![]))
         });
 
         // 主线程生成 tar
-        {
-            let mut builder = tar::Builder::new(writer);
-            if f_path.is_dir() {
-                builder
-                    .append_dir_all(f_path.file_name().unwrap(), f_path)
-                    .map_err(|_| RET_TAR_ERROR)?;
-            } else {
-                builder
-                    .append_path_with_name(f_path, f_path.file_name().unwrap())
-                    .map_err(|_| RET_TAR_ERROR)?;
+        let mut packing = Packing::default();
+        let built = {
+            let mut builder = tar::Builder::new(CountingWriter::new(writer));
+            builder.sparse(codec.policy.sparse);
+            append_tree(
+                &mut builder,
+                f_path,
+                Path::new(f_name),
+                codec,
+                &mut packing,
+                on_entry,
+            )
+            .and_then(|_| match codec.merge {
+                true => carry_over(&mut builder, output_path, &mut packing, on_entry),
+                false => Ok(()),
+            })
+            .and_then(|_| builder.finish())
+        };
+
+        // A failing compressor breaks the pipe, so its error is the more precise one
+        let (mut output_file, frames) = compressor
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("compressor thread panicked")))
+            .map_err(err)?;
+        built.map_err(err)?;
+        match frame_size {
+            Some(_) => {
+                let entries = packing.entries;
+                Index { frames, entries }.write_to(&mut output_file)
+            }
+            None => {
+                let size = packing.entries.iter().map(|e| e.size).sum();
+                frame::write_unpacked_size(&mut output_file, size)
             }
-            builder.finish().map_err(|_| RET_TAR_ERROR)?;
         }
+        .map_err(err)?;
+        decisions.extend(packing.decisions);
 
-        compressor.join().map_err(|_| RET_TAR_ERROR)?;
+        // Only a complete stream gets the final name
+        output_file.commit().map_err(err)?;
+        Ok(original_bytes)
     } else {
-        // Decompression path: zstd -> tar file -> unpack
-        let file_stem = f_path.file_stem().unwrap().to_str().unwrap();
-        let tar_path = target.join(format!("{file_stem}.tar"));
+        // Decompression path: zstd -> tar -> unpack, streamed in a single pass
+        let err = |e| Error::io(Operation::Extract, f_path, e);
+        let workers = codec.zstd_workers as usize;
+        let members = codec.members;
+        let index = match workers > 1 || !members.is_all() {
+            true => Index::read(f_path).map_err(err)?,
+            false => None,
+        };
 
-        // First decompress to .tar file
+        // The index tells where the first selected entry starts and how many follow
+        if let Some(index) = &index
+            && !members.is_all()
         {
-            let input_file = File::open(f_path).map_err(|_| RET_TAR_ERROR)?;
-            let output_file = File::create(&tar_path).map_err(|_| RET_TAR_ERROR)?;
-            zstd::stream::copy_decode(input_file, output_file).map_err(|_| RET_TAR_ERROR)?;
+            let selected: Vec<_> = index
+                .entries
+                .iter()
+                .filter(|e| members.matches(&e.path))
+                .collect();
+            let Some(first) = selected.first() else {
+                return Err(err(no_member(members)));
+            };
+            let frame = index
+                .frame_at(first.offset)
+                .ok_or_else(|| err(io::Error::other("index entry lies past the last frame")))?;
+            let mut decoder = open_decoder_at(f_path, frame.compressed_offset).map_err(err)?;
+            copy(
+                &mut (&mut decoder).take(first.offset - frame.offset),
+                &mut io::sink(),
+            )
+            .map_err(err)?;
+            let mut archive = tar::Archive::new(decoder);
+            let limit = Some(selected.len());
+            return unpack_archive(&mut archive, target, codec, limit, on_entry, decisions)
+                .map_err(err);
         }
+        let Some(index) = index.filter(|index| index.frames.len() > 1 && members.is_all()) else {
+            let decoder = open_decoder(f_path).map_err(err)?;
+            let mut archive = tar::Archive::new(decoder);
+            return unpack_archive(&mut archive, target, codec, None, on_entry, decisions)
+                .map_err(err);
+        };
+
+        // Independent frames are decoded side by side, and unpacked in order
+        let (reader, mut writer) = pipe::pipe();
+        let archive_path = f_path.to_path_buf();
+        let decoder = thread::spawn(move || {
+            seekable::decode_frames(&archive_path, &index, workers, &mut writer)
+        });
+        let mut archive = tar::Archive::new(reader);
+        let unpacked = unpack_archive(&mut archive, target, codec, None, on_entry, decisions);
+        // Unpacking may stop early, reading on lets the decoder finish
+        let mut reader = archive.into_inner();
+        let _ = copy(&mut reader, &mut io::sink());
+        decoder
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("decoder thread panicked")))
+            .map_err(err)?;
+        unpacked.map_err(err)
+    }
+}
+
+/// What [`append_tree`] keeps track of across the entries of one archive
+#[derive(Default)]
+struct Packing {
+    names: Names,
+    /// First name of each file with several names, by device and inode
+    links: HashMap<(u64, u64), PathBuf>,
+    /// Each entry and where its header starts in the tar stream
+    entries: Vec<EntryInfo>,
+    /// Entries the policy of the codec decided about
+    decisions: Vec<EntryDecision>,
+}
+
+impl Packing {
+    fn decide(&mut self, name: &Path, decision: Decision, link: Option<PathBuf>) {
+        self.decisions.push(EntryDecision {
+            path: name.to_path_buf(),
+            decision,
+            link,
+        });
+    }
+}
 
-        // Then unpack the tar file
-        let tar_file = File::open(&tar_path).map_err(|_| RET_TAR_ERROR)?;
-        let mut archive = tar::Archive::new(tar_file);
-        archive.unpack(target).map_err(|_| RET_TAR_ERROR)?;
+/// Append `path` as `name` and everything below it, in name order like
+/// `tar::Builder::append_dir_all`
+///
+/// Symlinks, hard links, sparse files and special files are archived as
+/// the entry policy of `codec` says, each decision going to `packing`.
+/// The attributes `codec` captures go in a PAX header ahead of the entry.
+fn append_tree<W: Write>(
+    builder: &mut tar::Builder<CountingWriter<W>>,
+    path: &Path,
+    name: &Path,
+    codec: &Codec,
+    packing: &mut Packing,
+    on_entry: &mut dyn FnMut(&Path),
+) -> io::Result<()> {
+    let (policy, attrs) = (&codec.policy, &codec.attrs);
+    let mut metadata = fs::symlink_metadata(path)?;
+    let mut link = None;
+    if metadata.is_symlink() {
+        let target = fs::read_link(path)?;
+        // A dangling symlink has nothing to follow, it is stored as it is
+        match (policy.symlinks, fs::metadata(path)) {
+            (SymlinkPolicy::Follow, Ok(followed)) => {
+                packing.decide(name, Decision::SymlinkFollowed, Some(target));
+                metadata = followed;
+            }
+            (SymlinkPolicy::Follow, Err(e)) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e);
+            }
+            _ => link = Some((tar::EntryType::Symlink, target)),
+        }
+    }
+    let special = policy::special_of(metadata.file_type());
+    if let Some(special) = special
+        && !policy.stores(special)
+    {
+        packing.decide(name, Decision::SpecialSkipped, None);
+        return Ok(());
+    }
+    if let Some(id) = policy::link_id(&metadata) {
+        match packing.links.get(&id) {
+            Some(first) if policy.hardlinks == HardlinkPolicy::Keep => {
+                link = Some((tar::EntryType::Link, first.clone()));
+            }
+            Some(first) => packing.decide(name, Decision::HardlinkCopied, Some(first.clone())),
+            None => {
+                packing.links.insert(id, name.to_path_buf());
+            }
+        }
+    }
 
-        // Clean up the intermediate tar file
-        std::fs::remove_file(&tar_path).map_err(|_| RET_TAR_ERROR)?;
+    on_entry(name);
+    packing.entries.push(EntryInfo {
+        path: name.to_path_buf(),
+        offset: builder.get_ref().count(),
+        size: match (&link, metadata.is_file()) {
+            (None, true) => metadata.len(),
+            _ => 0,
+        },
+    });
+    if attrs.captures() {
+        attrs::append_pax(builder, path, &metadata, attrs, &mut packing.names)?;
+    }
+    if let Some((entry_type, target)) = link {
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&metadata);
+        header.set_entry_type(entry_type);
+        header.set_size(0);
+        builder.append_link(&mut header, name, &target)?;
+        let decision = match entry_type {
+            tar::EntryType::Link => Decision::HardlinkKept,
+            _ => Decision::SymlinkStored,
+        };
+        packing.decide(name, decision, Some(target));
+        return Ok(());
+    }
+    if let Some(special) = special {
+        let mut header = policy::special_header(&metadata, special)?;
+        builder.append_data(&mut header, name, io::empty())?;
+        packing.decide(name, Decision::SpecialStored, None);
+        return Ok(());
+    }
+    if !metadata.is_dir() {
+        if policy.sparse && policy::is_sparse(&metadata) {
+            packing.decide(name, Decision::Sparse, None);
+        }
+        return builder.append_path_with_name(path, name);
     }
+    builder.append_dir(name, path)?;
+    let mut children = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        append_tree(
+            builder,
+            &child.path(),
+            &name.join(child.file_name()),
+            codec,
+            packing,
+            on_entry,
+        )?;
+    }
+    Ok(())
+}
 
+/// Append the entries of the archive at `old` that `packing` has no entry
+/// of yet, with their PAX records, merging the item into it
+///
+/// Sparse entries are written out whole.
+fn carry_over<W: Write>(
+    builder: &mut tar::Builder<CountingWriter<W>>,
+    old: &Path,
+    packing: &mut Packing,
+    on_entry: &mut dyn FnMut(&Path),
+) -> io::Result<()> {
+    let packed: HashSet<_> = packing.entries.iter().map(|e| e.path.clone()).collect();
+    let mut archive = tar::Archive::new(open_decoder(old)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name: PathBuf = entry.path()?.components().collect();
+        if packed.contains(&name) {
+            continue;
+        }
+        on_entry(&name);
+        let mut header = entry.header().clone();
+        let link = entry.link_name()?.map(|link| link.into_owned());
+        packing.entries.push(EntryInfo {
+            path: name.clone(),
+            offset: builder.get_ref().count(),
+            size: match link {
+                None => entry.size(),
+                Some(_) => 0,
+            },
+        });
+        attrs::copy_pax(builder, &mut entry)?;
+        match link {
+            Some(link) => builder.append_link(&mut header, &name, &link)?,
+            None => {
+                if header.entry_type().is_gnu_sparse() {
+                    header.set_entry_type(tar::EntryType::Regular);
+                }
+                header.set_size(entry.size());
+                builder.append_data(&mut header, &name, &mut entry)?;
+            }
+        }
+    }
     Ok(())
 }
 
+/// Unpack the entries the members of `codec` select like `tar::Archive::unpack`,
+/// with the attributes it restores, counting the file bytes
+///
+/// With `limit`, reading stops after that many selected entries.
+fn unpack_archive<R: Read>(
+    archive: &mut tar::Archive<R>,
+    target: &Path,
+    codec: &Codec,
+    limit: Option<usize>,
+    on_entry: &mut dyn FnMut(&Path),
+    decisions: &mut Vec<EntryDecision>,
+) -> io::Result<u64> {
+    let (members, attrs) = (codec.members, &codec.attrs);
+    attrs.apply(archive);
+    create_dir_all(target)?;
+    let target = &target.canonicalize().unwrap_or(target.to_path_buf());
+    let mut unpacking = Unpacking {
+        placed: HashMap::new(),
+        decisions,
+    };
+    let mut unpacked_bytes = 0;
+    let mut selected = 0;
+
+    // Directories last, so their permissions and mtimes are not disturbed by their contents
+    let mut directories = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !members.matches(&path) {
+            continue;
+        }
+        selected += 1;
+        safety::check_path(&path)?;
+        if entry.header().entry_type().is_hard_link()
+            && let Some(link) = entry.link_name()?
+        {
+            safety::check_path(&link)?;
+        }
+        if let Some(top) = &codec.top {
+            safety::check_top(&path, top)?;
+        }
+        on_entry(&path);
+        let owner = attrs::owner_of(&mut entry, attrs)?;
+        if entry.header().entry_type() == tar::EntryType::Directory {
+            directories.push((entry, owner));
+        } else {
+            unpacked_bytes += entry.size();
+            unpack_entry(&mut entry, target, codec, owner, &mut unpacking)?;
+        }
+        if limit == Some(selected) {
+            break;
+        }
+    }
+    if selected == 0 && !members.is_all() {
+        return Err(no_member(members));
+    }
+    directories.sort_by(|(a, _), (b, _)| b.path_bytes().cmp(&a.path_bytes()));
+    for (mut dir, owner) in directories {
+        unpack_entry(&mut dir, target, codec, owner, &mut unpacking)?;
+    }
+
+    Ok(unpacked_bytes)
+}
+
+/// What [`unpack_archive`] keeps track of across the entries of one archive
+struct Unpacking<'a> {
+    /// Where each entry unpacked under another name went, for hard links to it
+    placed: HashMap<PathBuf, PathBuf>,
+    /// Entries the existing policy of the codec decided about
+    decisions: &'a mut Vec<EntryDecision>,
+}
+
+impl Unpacking<'_> {
+    fn decide(&mut self, name: &Path, decision: Decision, link: Option<PathBuf>) {
+        self.decisions.push(EntryDecision {
+            path: name.to_path_buf(),
+            decision,
+            link,
+        });
+    }
+}
+
+/// Unpack one entry below `target`, at the path the members of `codec` cut
+/// it to, and give it `owner`
+fn unpack_entry<R: Read>(
+    entry: &mut tar::Entry<R>,
+    target: &Path,
+    codec: &Codec,
+    owner: Option<(u32, u32)>,
+    unpacking: &mut Unpacking,
+) -> io::Result<()> {
+    let Some(dst) = place_entry(entry, target, codec, unpacking)? else {
+        return Ok(());
+    };
+    match owner {
+        Some(owner) => attrs::set_owner(&dst, owner, entry.header().mode()?, &codec.attrs),
+        None => Ok(()),
+    }
+}
+
+/// Unpack one entry below the canonical `target`, returning where it went
+///
+/// Nothing is written through a symlink leading out of `target`, and an
+/// existing file in the way is handled by the existing policy of `codec`.
+/// Directories merge with existing ones.
+fn place_entry<R: Read>(
+    entry: &mut tar::Entry<R>,
+    target: &Path,
+    codec: &Codec,
+    unpacking: &mut Unpacking,
+) -> io::Result<Option<PathBuf>> {
+    let members = codec.members;
+    let Some(name) = members.strip(&entry.path()?) else {
+        return Ok(None);
+    };
+    let mut dst = target.join(&name);
+    if let Some(parent) = dst.parent() {
+        safety::check_inside(parent, target)?;
+        create_dir_all(parent)?;
+    }
+    let entry_type = entry.header().entry_type();
+    if !entry_type.is_dir()
+        && let Ok(existing) = fs::symlink_metadata(&dst)
+    {
+        match codec.existing {
+            ExistingPolicy::Skip => {
+                unpacking.decide(&name, Decision::ExistingSkipped, None);
+                return Ok(None);
+            }
+            ExistingPolicy::KeepBoth => {
+                dst = safety::free_name(&dst);
+                let renamed = name.with_file_name(dst.file_name().unwrap_or_default());
+                unpacking.placed.insert(name.clone(), dst.clone());
+                unpacking.decide(&name, Decision::ExistingKeptBoth, Some(renamed));
+            }
+            ExistingPolicy::Fail => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{dst:?} exists already"),
+                ));
+            }
+            ExistingPolicy::Overwrite => {
+                if existing.is_dir() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("{dst:?} is a directory"),
+                    ));
+                }
+                remove_file(&dst)?;
+            }
+        }
+    }
+
+    // A hard link names another entry, which was cut and maybe renamed the same way
+    if entry_type.is_hard_link() {
+        let Some(src) = entry.link_name()?.and_then(|link| members.strip(&link)) else {
+            return Ok(None);
+        };
+        let src = match unpacking.placed.get(&src) {
+            Some(placed) => placed.clone(),
+            None => target.join(src),
+        };
+        fs::hard_link(src, &dst)?;
+        return Ok(Some(dst));
+    }
+    // `unpack` would make an empty file of it
+    if entry_type.is_fifo() || entry_type.is_character_special() || entry_type.is_block_special() {
+        policy::make_special(entry, &dst)?;
+        return Ok(Some(dst));
+    }
+    entry.unpack(&dst)?;
+    Ok(Some(dst))
+}
+
+fn no_member(members: &Members) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no entry matches {:?}", members.patterns()),
+    )
+}
+
 /// Listing files in a directory to be compressed
 mod dir_listing {
+    use crate::auxiliary::AtomicFile;
     use std::fs::{self, DirEntry};
     use std::io::{self, Write};
     use std::path::Path;
     use std::time::SystemTime;
 
+    /// The listing of `dir_path` as a string, for embedding
+    pub fn render_listing(dir_path: &Path, max_depth: u8) -> io::Result<String> {
+        let mut output = vec![];
+        list_directory(dir_path, &mut output, max_depth, 0)?;
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
     pub fn generate_listing(
         dir_path: &Path,
         output_path: &Path,
         max_depth: u8,
         dry_run: bool,
     ) -> Result<(), io::Error> {
-        let mut output: Box<dyn Write> = match dry_run {
-            false => Box::new(fs::File::create(output_path)?),
-            true => Box::new(io::sink()),
-        };
-        list_directory(dir_path, &mut output, max_depth, 0)
+        if dry_run {
+            return list_directory(dir_path, &mut io::sink(), max_depth, 0);
+        }
+        let mut output = AtomicFile::create(output_path)?;
+        list_directory(dir_path, &mut output, max_depth, 0)?;
+        output.commit()
     }
 
     fn list_directory(
@@ -297,7 +1162,7 @@ mod dir_listing {
         Ok(())
     }
 
-    fn dir_size(path: &Path) -> io::Result<u64> {
+    pub(crate) fn dir_size(path: &Path) -> io::Result<u64> {
         fn walk_dir(path: &Path, total: &mut u64) -> io::Result<()> {
             for entry in fs::read_dir(path)? {
                 let entry = entry?;
@@ -317,7 +1182,7 @@ mod dir_listing {
         Ok(total)
     }
 
-    fn human_size(size: u64) -> String {
+    pub(crate) fn human_size(size: u64) -> String {
         const UNITS: [&str; 6] = ["B", "KB", "MB", "GB", "TB", "PB"];
         let mut size = size as f64;
         let mut unit_idx = 0;
@@ -330,33 +1195,26 @@ mod dir_listing {
         format!("{:.1}{}", size, UNITS[unit_idx])
     }
 
-    fn system_time_to_date_time(time: SystemTime) -> String {
+    pub(crate) fn system_time_to_date_time(time: SystemTime) -> String {
         use chrono::{DateTime, Local};
         let datetime: DateTime<Local> = time.into();
         datetime.format("%Y-%m-%d %H:%M:%S").to_string()
     }
 }
 
-/// Delete unneeded files, and print any error
-fn f_remove_print(f_path: &Path, f_is_dir: bool) -> Result<(), std::io::Error> {
-    if f_is_dir {
-        match remove_dir_all(f_path) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                eprintln!(
-                    "Error, couldn't remove original directory, {:?}: {e}",
-                    f_path
-                );
-                Err(e)
-            }
-        }
-    } else {
-        match remove_file(f_path) {
-            Ok(_) => Ok(()),
-            Err(e) => {
-                eprintln!("Error, couldn't remove original file, {:?}: {e}", f_path);
-                Err(e)
-            }
-        }
+/// Delete unneeded files, and report any error
+fn f_remove_print(f_path: &Path, f_is_dir: bool, events: &dyn Reporter) -> io::Result<()> {
+    let removed = match f_is_dir {
+        true => remove_dir_all(f_path),
+        false => remove_file(f_path),
+    };
+    match &removed {
+        Ok(_) => events.event(&Event::Removed { path: f_path }),
+        Err(e) => events.event(&Event::Error(&format!(
+            "Couldn't remove original {}, {:?}: {e}",
+            if f_is_dir { "directory" } else { "file" },
+            f_path
+        ))),
     }
+    removed
 }
@@ -0,0 +1,161 @@
+use crate::archiver::BatchOptions;
+use crate::error::{Error, Operation, Result};
+use glob::Pattern;
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Include and exclude patterns of a batch, compiled once
+///
+/// A name is skipped if any exclude pattern matches it. Otherwise it is
+/// processed if there is no include pattern at all, or if any of them
+/// matches.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    #[cfg(feature = "regex")]
+    include_regex: Vec<Regex>,
+    #[cfg(feature = "regex")]
+    exclude_regex: Vec<Regex>,
+}
+
+impl Filter {
+    /// Compile the patterns of `options`, failing on the first invalid one
+    pub fn new(options: &BatchOptions) -> Result<Self> {
+        Ok(Self {
+            include: compile_globs(&options.include)?,
+            exclude: compile_globs(&options.exclude)?,
+            #[cfg(feature = "regex")]
+            include_regex: compile_regexes(&options.include_regex)?,
+            #[cfg(feature = "regex")]
+            exclude_regex: compile_regexes(&options.exclude_regex)?,
+        })
+    }
+
+    /// Whether the item at `file_path` passes the filter, by its file name
+    pub fn matches(&self, file_path: &Path) -> bool {
+        let file_name = file_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+
+        // Check exclude patterns first
+        if self.exclude.iter().any(|p| p.matches(file_name)) {
+            return false;
+        }
+        #[cfg(feature = "regex")]
+        if self.exclude_regex.iter().any(|r| r.is_match(file_name)) {
+            return false;
+        }
+
+        // Default behavior: include everything if no include pattern specified
+        #[cfg(feature = "regex")]
+        let (no_include, regex_included) = (
+            self.include.is_empty() && self.include_regex.is_empty(),
+            self.include_regex.iter().any(|r| r.is_match(file_name)),
+        );
+        #[cfg(not(feature = "regex"))]
+        let (no_include, regex_included) = (self.include.is_empty(), false);
+
+        no_include || regex_included || self.include.iter().any(|p| p.matches(file_name))
+    }
+}
+
+/// Entries of an archive selected for extraction, and how their paths are cut
+///
+/// An entry is selected if a member pattern matches its path or the path of
+/// a directory above it. `*` matches across `/`, as in `tar --wildcards`.
+/// Without member patterns every entry is selected.
+#[derive(Debug, Clone, Default)]
+pub struct Members {
+    patterns: Vec<Pattern>,
+    strip_components: usize,
+}
+
+impl Members {
+    /// Compile the member patterns of `options`, failing on the first invalid one
+    pub fn new(options: &BatchOptions) -> Result<Self> {
+        Ok(Self {
+            patterns: compile_globs(&options.members)?,
+            strip_components: options.strip_components,
+        })
+    }
+
+    /// Whether every entry is selected
+    pub fn is_all(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Whether entry paths are cut
+    pub fn strips(&self) -> bool {
+        self.strip_components > 0
+    }
+
+    /// Whether the entry at `path` is selected
+    pub fn matches(&self, path: &Path) -> bool {
+        self.is_all()
+            || path
+                .ancestors()
+                .filter(|p| !p.as_os_str().is_empty())
+                .any(|p| self.patterns.iter().any(|pattern| pattern.matches_path(p)))
+    }
+
+    /// Where the entry at `path` goes below the target, `None` for an entry
+    /// stripped away entirely or with a path leaving the target
+    pub fn strip(&self, path: &Path) -> Option<PathBuf> {
+        let stripped = path
+            .components()
+            .filter(|c| *c != Component::CurDir)
+            .skip(self.strip_components)
+            .map(|c| match c {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect::<Option<PathBuf>>()?;
+        (!stripped.as_os_str().is_empty()).then_some(stripped)
+    }
+
+    /// Patterns as given, for messages
+    pub fn patterns(&self) -> Vec<&str> {
+        self.patterns.iter().map(Pattern::as_str).collect()
+    }
+}
+
+/// Read a pattern list, one pattern per line, skipping blank lines and `#` comments
+pub fn read_pattern_file(path: &Path) -> Result<Vec<String>> {
+    let contents =
+        fs::read_to_string(path).map_err(|e| Error::io(Operation::ReadPatterns, path, e))?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect())
+}
+
+fn compile_globs(patterns: &[String]) -> Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Pattern::new(pattern).map_err(|e| Error::InvalidPattern {
+                pattern: pattern.clone(),
+                message: e.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "regex")]
+fn compile_regexes(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| Error::InvalidPattern {
+                pattern: pattern.clone(),
+                message: e.to_string(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,192 @@
+use crate::dict::dict_file_name;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use zstd::zstd_safe;
+
+/// Largest zstd frame header
+const FRAME_HEADER_MAX: usize = 18;
+const FRAME_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// Magics of skippable frames, 16 of them differing in the low 4 bits
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A50;
+
+/// Magic of the skippable frame ending an archive without index, holding
+/// the size of the files in it
+const SIZE_MAGIC: u32 = 0x184D_2A5C;
+/// Last bytes of an archive ending with its unpacked size
+const SIZE_TAG: &[u8; 4] = b"zsts";
+/// Length of the whole size frame: magic, length, size and tag
+const SIZE_FRAME_LEN: u64 = 20;
+
+/// Window log a decoder accepts without being told otherwise
+const WINDOW_LOG_DEFAULT_MAX: u32 = 27;
+
+/// Decoder of one archive
+pub type ArchiveDecoder = zstd::stream::Decoder<'static, BufReader<File>>;
+
+/// What the header of one zstd frame tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameHeader {
+    /// ID of the dictionary the frame was made with
+    pub dict_id: Option<u32>,
+    /// Smallest window log holding the window of the frame
+    pub window_log: Option<u32>,
+    /// Size of the decoded frame, if the encoder knew it
+    pub content_size: Option<u64>,
+    /// Whether the frame ends with a checksum of its content
+    pub checksum: bool,
+}
+
+impl FrameHeader {
+    /// Read the header of the frame starting at `offset` of `archive_path`,
+    /// or of the first one after the skippable frames there
+    pub fn read(archive_path: &Path, offset: u64) -> io::Result<Self> {
+        let mut file = File::open(archive_path)?;
+        Self::read_from(&mut file, offset)
+    }
+
+    fn read_from(file: &mut File, mut offset: u64) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(FRAME_HEADER_MAX);
+        loop {
+            file.seek(SeekFrom::Start(offset))?;
+            header.clear();
+            file.take(FRAME_HEADER_MAX as u64)
+                .read_to_end(&mut header)?;
+            match header.get(..8) {
+                Some(skippable)
+                    if u32::from_le_bytes(skippable[..4].try_into().unwrap()) & !0x0f
+                        == SKIPPABLE_MAGIC =>
+                {
+                    let len = u32::from_le_bytes(skippable[4..].try_into().unwrap());
+                    offset += 8 + u64::from(len);
+                }
+                _ => break,
+            }
+        }
+        if header.get(..4) != Some(&FRAME_MAGIC[..]) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a zstd frame",
+            ));
+        }
+        Ok(Self {
+            dict_id: zstd_safe::get_dict_id_from_frame(&header).map(|id| id.get()),
+            window_log: window_log(&header),
+            content_size: zstd_safe::get_frame_content_size(&header).ok().flatten(),
+            checksum: header
+                .get(4)
+                .is_some_and(|descriptor| descriptor & 0x04 != 0),
+        })
+    }
+}
+
+/// Append a skippable frame recording `size`, the bytes of the files in
+/// the archive, to `writer`
+///
+/// Seekable archives end with their index instead, which sizes every entry.
+pub fn write_unpacked_size<W: Write>(writer: &mut W, size: u64) -> io::Result<()> {
+    writer.write_all(&SIZE_MAGIC.to_le_bytes())?;
+    writer.write_all(&(SIZE_FRAME_LEN as u32 - 8).to_le_bytes())?;
+    writer.write_all(&size.to_le_bytes())?;
+    writer.write_all(SIZE_TAG)
+}
+
+/// Unpacked size recorded at the end of `archive_path`, `None` for an
+/// archive without one
+pub fn read_unpacked_size(archive_path: &Path) -> io::Result<Option<u64>> {
+    let mut file = File::open(archive_path)?;
+    if file.metadata()?.len() < SIZE_FRAME_LEN {
+        return Ok(None);
+    }
+    file.seek(SeekFrom::End(-(SIZE_FRAME_LEN as i64)))?;
+    let mut frame = [0; SIZE_FRAME_LEN as usize];
+    file.read_exact(&mut frame)?;
+    match frame[..4] == SIZE_MAGIC.to_le_bytes() && &frame[16..] == SIZE_TAG {
+        true => Ok(Some(u64::from_le_bytes(frame[8..16].try_into().unwrap()))),
+        false => Ok(None),
+    }
+}
+
+/// Open the zstd stream of `archive_path`
+///
+/// An archive made with a dictionary names it by ID in its frame header; the
+/// sidecar is looked for beside the archive and in the directories above. An
+/// archive made with a window above the decoder default, e.g. by long-distance
+/// matching, gets the decoder limit raised to match.
+pub fn open_decoder(archive_path: &Path) -> io::Result<ArchiveDecoder> {
+    open_decoder_at(archive_path, 0)
+}
+
+/// Open the zstd stream of `archive_path` from the frame starting at `offset`
+pub fn open_decoder_at(archive_path: &Path, offset: u64) -> io::Result<ArchiveDecoder> {
+    let mut file = File::open(archive_path)?;
+    // Anything that is no zstd frame is left for the decoder to reject
+    let header = FrameHeader::read_from(&mut file, offset).unwrap_or_default();
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut decoder = match header.dict_id {
+        Some(id) => {
+            let dict = find_dictionary(archive_path, &dict_file_name(id))?;
+            ArchiveDecoder::with_dictionary(BufReader::new(file), &dict)?
+        }
+        None => ArchiveDecoder::new(file)?,
+    };
+    if let Some(window_log) = header.window_log
+        && window_log > WINDOW_LOG_DEFAULT_MAX
+    {
+        decoder.window_log_max(window_log)?;
+    }
+    Ok(decoder)
+}
+
+/// Read the sidecar `name` beside `archive_path` or in a directory above
+fn find_dictionary(archive_path: &Path, name: &str) -> io::Result<Vec<u8>> {
+    archive_path
+        .ancestors()
+        .skip(1)
+        .find_map(|dir| {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            fs::read(dir.join(name)).ok()
+        })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("dictionary {name} of the archive not found"),
+            )
+        })
+}
+
+/// Smallest window log holding the window of the frame starting with `header`
+fn window_log(header: &[u8]) -> Option<u32> {
+    if header.get(..4)? != FRAME_MAGIC {
+        return None;
+    }
+    let descriptor = *header.get(4)?;
+    let single_segment = descriptor & 0x20 != 0;
+    let window_size = if single_segment {
+        // The window is the whole content, its size follows the dictionary ID
+        let dict_id_size = [0, 1, 2, 4][(descriptor & 0x03) as usize];
+        let start = 5 + dict_id_size;
+        match descriptor >> 6 {
+            0 => u64::from(*header.get(start)?),
+            1 => {
+                u64::from(u16::from_le_bytes(
+                    header.get(start..start + 2)?.try_into().ok()?,
+                )) + 256
+            }
+            2 => u64::from(u32::from_le_bytes(
+                header.get(start..start + 4)?.try_into().ok()?,
+            )),
+            _ => u64::from_le_bytes(header.get(start..start + 8)?.try_into().ok()?),
+        }
+    } else {
+        let window = *header.get(5)?;
+        let base = 1u64 << (10 + (window >> 3));
+        base + base / 8 * u64::from(window & 0x07)
+    };
+    Some(u64::BITS - window_size.saturating_sub(1).leading_zeros())
+}
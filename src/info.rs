@@ -0,0 +1,116 @@
+use crate::dict::dict_file_name;
+use crate::embed::Embedded;
+use crate::exec::human_size;
+use crate::frame::FrameHeader;
+use crate::seekable::Index;
+use serde::Serialize;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What an archive tells about itself without being decoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchiveInfo {
+    pub archive: PathBuf,
+    /// Size of the archive file
+    pub bytes: u64,
+    /// Size of the tar stream, if the frame header or the index tells it
+    pub content_bytes: Option<u64>,
+    /// ID of the zstd dictionary needed to decode the archive
+    pub dict_id: Option<u32>,
+    /// Window log the decoder needs
+    pub window_log: Option<u32>,
+    /// Whether the frames end with a checksum
+    pub checksum: bool,
+    /// Number of zstd frames, for seekable archives
+    pub frames: Option<usize>,
+    /// Number of tar entries, for seekable archives
+    pub entries: Option<usize>,
+    /// Listing, manifest and metadata made with `--embed`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub embedded: Option<Embedded>,
+}
+
+impl ArchiveInfo {
+    /// Read the embedded data, the first frame header and the index of `archive_path`
+    pub fn read(archive_path: &Path) -> io::Result<Self> {
+        let bytes = archive_path.metadata()?.len();
+        let header = FrameHeader::read(archive_path, 0)?;
+        let index = Index::read(archive_path)?;
+        let content_bytes = match &index {
+            Some(index) => Some(index.frames.iter().map(|f| f.size).sum()),
+            None => header.content_size,
+        };
+        Ok(Self {
+            archive: archive_path.to_path_buf(),
+            bytes,
+            content_bytes,
+            dict_id: header.dict_id,
+            window_log: header.window_log,
+            checksum: header.checksum,
+            frames: index.as_ref().map(|index| index.frames.len()),
+            entries: index.as_ref().map(|index| index.entries.len()),
+            embedded: Embedded::read(archive_path)?,
+        })
+    }
+
+    /// Lines for the console
+    pub fn render(&self) -> String {
+        let mut out = format!("{:?}\n", self.archive);
+        let _ = writeln!(
+            out,
+            "    Size:       {} ({} bytes)",
+            human_size(self.bytes),
+            self.bytes
+        );
+        if let Some(content) = self.content_bytes {
+            let _ = write!(
+                out,
+                "    Content:    {} ({content} bytes)",
+                human_size(content)
+            );
+            if content > 0 {
+                let ratio = self.bytes as f64 / content as f64 * 100.0;
+                let _ = write!(out, ", {ratio:.1}%");
+            }
+            out.push('\n');
+        }
+        let format = match (self.frames, self.entries) {
+            (Some(frames), Some(entries)) => {
+                format!("seekable, {frames} frames, index of {entries} entries")
+            }
+            _ => "single stream".to_string(),
+        };
+        let _ = writeln!(out, "    Format:     {format}");
+        if let Some(window_log) = self.window_log {
+            let _ = writeln!(
+                out,
+                "    Window:     {} (log {window_log})",
+                human_size(1 << window_log)
+            );
+        }
+        if let Some(id) = self.dict_id {
+            let _ = writeln!(out, "    Dictionary: {}", dict_file_name(id));
+        }
+        let _ = writeln!(
+            out,
+            "    Checksum:   {}",
+            if self.checksum { "yes" } else { "no" }
+        );
+        if let Some(embedded) = &self.embedded {
+            let mut parts = vec!["metadata".to_string()];
+            if embedded.listing.is_some() {
+                parts.push("listing".to_string());
+            }
+            if !embedded.manifest.is_empty() {
+                parts.push(format!("manifest of {} entries", embedded.manifest.len()));
+            }
+            let _ = writeln!(out, "    Embedded:   {}", parts.join(", "));
+            let width = embedded.metadata.keys().map(String::len).max().unwrap_or(0);
+            for (key, value) in &embedded.metadata {
+                let _ = writeln!(out, "      {key:<width$} = {value}");
+            }
+        }
+        out
+    }
+}
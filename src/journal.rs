@@ -0,0 +1,213 @@
+use crate::archiver::Mode;
+use crate::error::{Error, Operation, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Name of the journal of compress runs in the output directory, `zst_`
+/// keeps it out of the batch items
+pub static S_JOURNAL: &str = "zst_journal.jsonl";
+
+/// Name of the journal of extract runs, apart so that they leave the
+/// journal of the compress run in the same directory alone
+pub static S_EXTRACT_JOURNAL: &str = "zst_journal-extract.jsonl";
+
+/// Name of the journal of `mode` runs
+pub fn journal_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::Extract => S_EXTRACT_JOURNAL,
+        _ => S_JOURNAL,
+    }
+}
+
+/// How far an item got, in the order the steps happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ItemState {
+    /// Work on the item began, no step is complete yet
+    Started,
+    /// The complete archive has its final name, or the archive was unpacked
+    Archived,
+    /// The archive was read back and matched the original
+    Verified,
+    /// The original was removed
+    Removed,
+    /// Nothing left to do
+    Done,
+}
+
+/// One line of the journal
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "lowercase")]
+enum Record {
+    Run { mode: Mode, source: PathBuf },
+    Item { item: PathBuf, state: ItemState },
+}
+
+/// Progress journal of a batch, one JSON line per step of an item
+///
+/// Each line is synced to disk before the run moves on, so after a crash
+/// the last state of an item tells exactly which steps are complete. Items
+/// get their first line when work on them begins.
+///
+/// The journal of a compress run stays after it, [`BatchOptions::restore`]
+/// reads it to put the items back. That of an extract run is only of use to
+/// resume it and is removed once every item succeeded.
+///
+/// [`BatchOptions::restore`]: crate::archiver::BatchOptions::restore
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    /// `None` in dry runs, which only read the journal
+    file: Option<Mutex<File>>,
+    /// Last state of each item in the journal that is resumed
+    previous: HashMap<PathBuf, ItemState>,
+}
+
+impl Journal {
+    /// Start a new journal for a `mode` run of the items in `source`, in
+    /// `dir`, replacing any earlier one
+    pub fn create(dir: &Path, mode: Mode, source: &Path) -> Result<Self> {
+        let path = dir.join(journal_name(mode));
+        let file = File::create(&path).map_err(|e| Error::io(Operation::WriteJournal, &path, e))?;
+        let journal = Self {
+            path,
+            file: Some(Mutex::new(file)),
+            previous: HashMap::new(),
+        };
+        journal.write(&Record::Run {
+            mode,
+            source: source.to_path_buf(),
+        })?;
+        Ok(journal)
+    }
+
+    /// Continue the journal in `dir`, which must be of a `mode` run
+    ///
+    /// Without a journal this is a new one. With `dry_run` nothing is written.
+    pub fn resume(dir: &Path, mode: Mode, source: &Path, dry_run: bool) -> Result<Self> {
+        let path = dir.join(journal_name(mode));
+        let previous = match load(&path) {
+            Ok(Run {
+                mode: journal_mode, ..
+            }) if journal_mode != mode => {
+                return Err(Error::io(
+                    Operation::ReadJournal,
+                    &path,
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("journal is of a {journal_mode:?} run, not {mode:?}"),
+                    ),
+                ));
+            }
+            Ok(run) => run.items,
+            Err(Error::Io { source: e, .. }) if e.kind() == io::ErrorKind::NotFound => {
+                return match dry_run {
+                    true => Ok(Self {
+                        path,
+                        file: None,
+                        previous: HashMap::new(),
+                    }),
+                    false => Self::create(dir, mode, source),
+                };
+            }
+            Err(e) => return Err(e),
+        };
+        let file = match dry_run {
+            true => None,
+            false => Some(Mutex::new(
+                OpenOptions::new()
+                    .append(true)
+                    .open(&path)
+                    .map_err(|e| Error::io(Operation::WriteJournal, &path, e))?,
+            )),
+        };
+        Ok(Self {
+            path,
+            file,
+            previous,
+        })
+    }
+
+    /// Where the journal is kept
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Last state of `item` in the resumed journal
+    pub fn previous(&self, item: &Path) -> Option<ItemState> {
+        self.previous.get(item).copied()
+    }
+
+    /// Record that `item` reached `state`, synced before returning
+    pub fn record(&self, item: &Path, state: ItemState) -> Result<()> {
+        self.write(&Record::Item {
+            item: item.to_path_buf(),
+            state,
+        })
+    }
+
+    /// Remove the journal, once nothing is left to resume
+    pub fn remove(self) -> Result<()> {
+        if self.file.is_none() {
+            return Ok(());
+        }
+        fs::remove_file(&self.path).map_err(|e| Error::io(Operation::WriteJournal, &self.path, e))
+    }
+
+    fn write(&self, record: &Record) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let line = serde_json::to_string(record).map_err(io::Error::other);
+        let mut file = file.lock().unwrap();
+        line.and_then(|line| writeln!(file, "{line}"))
+            .and_then(|_| file.sync_data())
+            .map_err(|e| Error::io(Operation::WriteJournal, &self.path, e))
+    }
+}
+
+/// What a journal tells about its run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Run {
+    pub mode: Mode,
+    /// Directory the items were read from
+    pub source: PathBuf,
+    /// Last state of each item, by its path relative to `source`
+    pub items: HashMap<PathBuf, ItemState>,
+}
+
+/// Read a journal
+pub fn load(path: &Path) -> Result<Run> {
+    let err = |e| Error::io(Operation::ReadJournal, path, e);
+    let reader = BufReader::new(File::open(path).map_err(err)?);
+    let mut run = None;
+    let mut states = HashMap::new();
+    for line in reader.lines() {
+        let line = line.map_err(err)?;
+        // A crash can cut the last line short, it never made it to disk
+        let Ok(record) = serde_json::from_str(&line) else {
+            continue;
+        };
+        match record {
+            Record::Run { mode, source } => run = Some((mode, source)),
+            Record::Item { item, state } => {
+                states.insert(item, state);
+            }
+        }
+    }
+    let (mode, source) = run.ok_or_else(|| {
+        err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no run record in journal",
+        ))
+    })?;
+    Ok(Run {
+        mode,
+        source,
+        items: states,
+    })
+}
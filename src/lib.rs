@@ -1,3 +1,23 @@
+pub mod archiver;
+pub mod attrs;
 pub mod auxiliary;
 pub mod batch_runner;
+pub mod config;
+pub mod dict;
+pub mod embed;
+pub mod error;
+pub mod events;
 pub mod exec;
+pub mod filter;
+pub mod frame;
+pub mod info;
+pub mod journal;
+pub mod list;
+pub mod manifest;
+pub mod params;
+pub mod policy;
+pub mod preflight;
+pub mod report;
+pub mod safety;
+pub mod seekable;
+pub mod verify;
@@ -0,0 +1,195 @@
+use crate::exec::{human_size, system_time_to_date_time};
+use crate::frame::open_decoder;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How the entries of an archive are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ListStyle {
+    /// Tree with sizes and dates, like the `_archived-filelist.txt` listing
+    #[default]
+    Tree,
+    /// One line per entry with mode, size, date and full path
+    Flat,
+}
+
+/// Type of a tar entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    Hardlink,
+    Other,
+}
+
+impl EntryKind {
+    /// Name of the kind, as in the JSON records
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EntryKind::File => "file",
+            EntryKind::Dir => "dir",
+            EntryKind::Symlink => "symlink",
+            EntryKind::Hardlink => "hardlink",
+            EntryKind::Other => "other",
+        }
+    }
+}
+
+/// One entry of an archive, as told by its tar header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchiveEntry {
+    pub path: PathBuf,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    /// Size of the file contents, 0 for anything else
+    pub size: u64,
+    /// Unix permission bits
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: u64,
+    /// Target of a symlink or hardlink
+    pub link: Option<PathBuf>,
+}
+
+/// Read the headers of all entries of `archive_path`, skipping their contents
+pub fn read_entries(archive_path: &Path) -> io::Result<Vec<ArchiveEntry>> {
+    let decoder = open_decoder(archive_path)?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut entries = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        let kind = match entry_type {
+            t if t.is_file() || t.is_gnu_sparse() => EntryKind::File,
+            t if t.is_dir() => EntryKind::Dir,
+            t if t.is_symlink() => EntryKind::Symlink,
+            t if t.is_hard_link() => EntryKind::Hardlink,
+            _ => EntryKind::Other,
+        };
+        entries.push(ArchiveEntry {
+            path: entry.path()?.components().collect(),
+            kind,
+            size: match kind {
+                EntryKind::File => entry.size(),
+                _ => 0,
+            },
+            mode: header.mode()? & 0o7777,
+            mtime: header.mtime()?,
+            link: entry.link_name()?.map(|link| link.into_owned()),
+        });
+    }
+    Ok(entries)
+}
+
+/// Render `entries` for the console in `style`
+pub fn render(entries: &[ArchiveEntry], style: ListStyle) -> String {
+    let mut out = String::new();
+    match style {
+        ListStyle::Flat => {
+            for entry in entries {
+                let _ = write!(
+                    out,
+                    "{} {:>10} {:<19} {}",
+                    mode_string(entry),
+                    entry.size,
+                    mtime_string(entry.mtime),
+                    entry.path.display()
+                );
+                if let Some(link) = &entry.link {
+                    let _ = write!(out, " -> {}", link.display());
+                }
+                out.push('\n');
+            }
+        }
+        ListStyle::Tree => {
+            let mut root = Node::default();
+            for entry in entries {
+                root.insert(entry);
+            }
+            root.render(&mut out, 0);
+        }
+    }
+    out
+}
+
+/// Directory of the tree, entries are placed by their path components
+#[derive(Default)]
+struct Node<'a> {
+    entry: Option<&'a ArchiveEntry>,
+    children: BTreeMap<OsString, Node<'a>>,
+}
+
+impl<'a> Node<'a> {
+    fn insert(&mut self, entry: &'a ArchiveEntry) {
+        // Parents missing from the archive still show up as directories
+        let node = entry.path.iter().fold(self, |node, name| {
+            node.children.entry(name.to_os_string()).or_default()
+        });
+        node.entry = Some(entry);
+    }
+
+    fn size(&self) -> u64 {
+        self.entry.map_or(0, |e| e.size) + self.children.values().map(Node::size).sum::<u64>()
+    }
+
+    fn render(&self, out: &mut String, depth: usize) {
+        for (name, child) in &self.children {
+            let is_dir =
+                !child.children.is_empty() || child.entry.is_some_and(|e| e.kind == EntryKind::Dir);
+            let tree_prefix = match depth {
+                0 => String::new(),
+                _ => "│  ".repeat(depth - 1) + "└──",
+            };
+            let modified = child
+                .entry
+                .map(|e| mtime_string(e.mtime))
+                .unwrap_or_default();
+            let _ = write!(
+                out,
+                "{:<19} {:>10} {}{} {}",
+                modified,
+                human_size(child.size()),
+                tree_prefix,
+                if is_dir { "┬" } else { "─" },
+                name.to_string_lossy()
+            );
+            if let Some(link) = child.entry.and_then(|e| e.link.as_ref()) {
+                let _ = write!(out, " -> {}", link.display());
+            }
+            out.push('\n');
+            child.render(out, depth + 1);
+        }
+    }
+}
+
+/// Permissions like `ls -l`, e.g. `drwxr-xr-x`
+fn mode_string(entry: &ArchiveEntry) -> String {
+    let kind = match entry.kind {
+        EntryKind::Dir => 'd',
+        EntryKind::Symlink => 'l',
+        EntryKind::Hardlink => 'h',
+        EntryKind::File => '-',
+        EntryKind::Other => '?',
+    };
+    let bits = "rwxrwxrwx"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| match entry.mode & (0o400 >> i) {
+            0 => '-',
+            _ => c,
+        });
+    std::iter::once(kind).chain(bits).collect()
+}
+
+fn mtime_string(mtime: u64) -> String {
+    system_time_to_date_time(SystemTime::UNIX_EPOCH + Duration::from_secs(mtime))
+}
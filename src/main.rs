@@ -1,3 +1,5 @@
-fn main() {
-    zst_compress::batch_runner::cli();
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    zst_compress::batch_runner::cli()
 }
@@ -0,0 +1,206 @@
+use crate::auxiliary::AtomicFile;
+use crate::list::EntryKind;
+use crate::policy::{self, EntryPolicy, SymlinkPolicy};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// File format of the `*_archived-manifest` written beside each archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManifestFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Comma-separated values with a header line
+    Csv,
+}
+
+impl ManifestFormat {
+    /// Extension of the manifest file
+    pub fn extension(self) -> &'static str {
+        match self {
+            ManifestFormat::Jsonl => "jsonl",
+            ManifestFormat::Csv => "csv",
+        }
+    }
+}
+
+/// One entry of an item, as archived
+///
+/// Unlike the `_archived-filelist.txt` tree, it goes to full depth and keeps
+/// exact values: sizes in bytes, mtimes to the nanosecond in UTC and the
+/// SHA-256 of each file, so an extraction can be checked against it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path of the entry in the archive
+    pub path: PathBuf,
+    #[serde(rename = "type")]
+    pub kind: EntryKind,
+    /// Size of the file contents, 0 for anything else
+    pub size: u64,
+    /// Modification time, RFC 3339 in UTC
+    pub mtime: String,
+    /// Unix permission bits
+    pub mode: u32,
+    /// Target of the symlink, stored as the entry or read through for its contents
+    pub link: Option<PathBuf>,
+    /// SHA-256 of the file contents, in hex
+    pub sha256: Option<String>,
+}
+
+/// Describe `path` as `name` and everything below it, in archive order
+///
+/// Symlinks are stored or followed and special files skipped as `policy`
+/// says, and every file is read once to hash it.
+pub fn build(path: &Path, name: &Path, policy: &EntryPolicy) -> io::Result<Vec<ManifestEntry>> {
+    let mut entries = vec![];
+    walk(path, name, policy, &mut entries)?;
+    Ok(entries)
+}
+
+fn walk(
+    path: &Path,
+    name: &Path,
+    policy: &EntryPolicy,
+    entries: &mut Vec<ManifestEntry>,
+) -> io::Result<()> {
+    let mut metadata = fs::symlink_metadata(path)?;
+    let link = match metadata.is_symlink() {
+        true => Some(fs::read_link(path)?),
+        false => None,
+    };
+    // Dangling symlinks are stored as they are
+    if link.is_some()
+        && policy.symlinks == SymlinkPolicy::Follow
+        && let Ok(followed) = fs::metadata(path)
+    {
+        metadata = followed;
+    }
+    if let Some(special) = policy::special_of(metadata.file_type())
+        && !policy.stores(special)
+    {
+        return Ok(());
+    }
+    let kind = match &metadata {
+        m if m.is_dir() => EntryKind::Dir,
+        m if m.is_file() => EntryKind::File,
+        m if m.is_symlink() => EntryKind::Symlink,
+        _ => EntryKind::Other,
+    };
+    entries.push(ManifestEntry {
+        path: name.to_path_buf(),
+        kind,
+        size: match kind {
+            EntryKind::File => metadata.len(),
+            _ => 0,
+        },
+        mtime: DateTime::<Utc>::from(metadata.modified()?)
+            .to_rfc3339_opts(SecondsFormat::Nanos, true),
+        mode: mode(&metadata),
+        link,
+        sha256: match kind {
+            EntryKind::File => Some(sha256(path)?),
+            _ => None,
+        },
+    });
+    if kind != EntryKind::Dir {
+        return Ok(());
+    }
+    let mut children = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        walk(
+            &child.path(),
+            &name.join(child.file_name()),
+            policy,
+            entries,
+        )?;
+    }
+    Ok(())
+}
+
+/// Write `entries` to `output_path` in `format`, replacing it atomically
+pub fn write(
+    entries: &[ManifestEntry],
+    output_path: &Path,
+    format: ManifestFormat,
+) -> io::Result<()> {
+    let mut output = AtomicFile::create(output_path)?;
+    match format {
+        ManifestFormat::Jsonl => {
+            for entry in entries {
+                serde_json::to_writer(&mut output, entry).map_err(io::Error::other)?;
+                output.write_all(b"\n")?;
+            }
+        }
+        ManifestFormat::Csv => {
+            writeln!(output, "path,type,size,mtime,mode,link,sha256")?;
+            for entry in entries {
+                writeln!(
+                    output,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(&entry.path.to_string_lossy()),
+                    entry.kind.as_str(),
+                    entry.size,
+                    entry.mtime,
+                    entry.mode,
+                    csv_field(
+                        &entry
+                            .link
+                            .as_ref()
+                            .map(|link| link.to_string_lossy())
+                            .unwrap_or_default()
+                    ),
+                    entry.sha256.as_deref().unwrap_or_default()
+                )?;
+            }
+        }
+    }
+    output.commit()
+}
+
+/// Quote `value` if it holds a separator, a quote or a line break
+fn csv_field(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+fn sha256(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 16];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => hasher.update(&buf[..n]),
+        }
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(unix)]
+fn mode(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+/// Permissions as tar stores them off Unix
+#[cfg(not(unix))]
+fn mode(metadata: &Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
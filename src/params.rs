@@ -0,0 +1,123 @@
+use crate::error::{Error, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::io;
+use std::io::Write;
+use zstd::zstd_safe::{self, CParameter};
+
+/// Highest level without ultra mode, as in the `zstd` CLI
+pub static LEVEL_MAX_NORMAL: i32 = 19;
+
+/// Window log used by long-distance matching without an explicit one, as in `zstd --long`
+pub static LONG_WINDOW_LOG: u32 = 27;
+
+/// Match finder of the zstd encoder, from fastest to strongest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    Fast,
+    Dfast,
+    Greedy,
+    Lazy,
+    Lazy2,
+    Btlazy2,
+    Btopt,
+    Btultra,
+    Btultra2,
+}
+
+impl From<Strategy> for zstd_safe::Strategy {
+    fn from(strategy: Strategy) -> Self {
+        match strategy {
+            Strategy::Fast => zstd_safe::Strategy::ZSTD_fast,
+            Strategy::Dfast => zstd_safe::Strategy::ZSTD_dfast,
+            Strategy::Greedy => zstd_safe::Strategy::ZSTD_greedy,
+            Strategy::Lazy => zstd_safe::Strategy::ZSTD_lazy,
+            Strategy::Lazy2 => zstd_safe::Strategy::ZSTD_lazy2,
+            Strategy::Btlazy2 => zstd_safe::Strategy::ZSTD_btlazy2,
+            Strategy::Btopt => zstd_safe::Strategy::ZSTD_btopt,
+            Strategy::Btultra => zstd_safe::Strategy::ZSTD_btultra,
+            Strategy::Btultra2 => zstd_safe::Strategy::ZSTD_btultra2,
+        }
+    }
+}
+
+/// Settings of the zstd encoder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ZstdParams {
+    /// Compression level, negative levels are the fast ones
+    pub level: i32,
+    /// Long-distance matching with this window log
+    pub long: Option<u32>,
+    /// Window log, overriding the one of the level or of `long`
+    pub window_log: Option<u32>,
+    /// Match finder, overriding the one of the level
+    pub strategy: Option<Strategy>,
+    /// Allow levels above [`LEVEL_MAX_NORMAL`]
+    pub ultra: bool,
+}
+
+impl Default for ZstdParams {
+    fn default() -> Self {
+        Self {
+            level: 5,
+            long: None,
+            window_log: None,
+            strategy: None,
+            ultra: false,
+        }
+    }
+}
+
+impl ZstdParams {
+    /// Check the settings against what the linked libzstd supports
+    pub fn validate(&self) -> Result<()> {
+        let min = zstd_safe::min_c_level();
+        let max = zstd_safe::max_c_level();
+        if self.level > LEVEL_MAX_NORMAL && self.level <= max && !self.ultra {
+            return Err(Error::InvalidOption(format!(
+                "zstd level {} needs ultra mode, levels above {LEVEL_MAX_NORMAL} use a lot of memory",
+                self.level
+            )));
+        }
+        if !(min..=max).contains(&self.level) {
+            return Err(Error::InvalidOption(format!(
+                "zstd level {} is out of range {min}..={max}",
+                self.level
+            )));
+        }
+
+        // libzstd rejects a window log out of its bounds instead of clamping it
+        let mut cctx = zstd_safe::CCtx::create();
+        for (name, window_log) in [("long", self.long), ("window log", self.window_log)] {
+            if let Some(window_log) = window_log {
+                cctx.set_parameter(CParameter::WindowLog(window_log))
+                    .map_err(|code| {
+                        Error::InvalidOption(format!(
+                            "zstd {name} {window_log}: {}",
+                            zstd_safe::get_error_name(code)
+                        ))
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set up `encoder` with these settings, the level is set on creation
+    pub(crate) fn apply<W: Write>(
+        &self,
+        encoder: &mut zstd::stream::Encoder<'_, W>,
+    ) -> io::Result<()> {
+        if let Some(long) = self.long {
+            encoder.long_distance_matching(true)?;
+            encoder.window_log(long)?;
+        }
+        if let Some(window_log) = self.window_log {
+            encoder.window_log(window_log)?;
+        }
+        if let Some(strategy) = self.strategy {
+            encoder.set_parameter(CParameter::Strategy(strategy.into()))?;
+        }
+        Ok(())
+    }
+}
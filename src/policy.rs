@@ -0,0 +1,275 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{FileType, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What compression does with a symlink
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Store the link itself, extraction recreates it
+    #[default]
+    Store,
+    /// Archive what the link points to in its place, like `tar -h`;
+    /// dangling links are stored as links
+    Follow,
+}
+
+/// What compression does with a file already archived under another name
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HardlinkPolicy {
+    /// Store a hard link entry naming the first one, without the contents
+    #[default]
+    Keep,
+    /// Store the contents again, extraction makes separate files
+    Copy,
+}
+
+/// What compression does with FIFOs, sockets and device nodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpecialPolicy {
+    /// Leave them out of the archive
+    #[default]
+    Skip,
+    /// Store FIFOs and devices as such; sockets cannot be archived and are left out
+    Store,
+}
+
+/// How entries that are not plain files or directories are archived
+///
+/// Each entry handled this way is reported as an [`EntryDecision`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPolicy {
+    pub symlinks: SymlinkPolicy,
+    pub hardlinks: HardlinkPolicy,
+    /// Leave the holes of sparse files out of the archive
+    pub sparse: bool,
+    pub special: SpecialPolicy,
+}
+
+impl Default for EntryPolicy {
+    fn default() -> Self {
+        Self {
+            symlinks: SymlinkPolicy::default(),
+            hardlinks: HardlinkPolicy::default(),
+            sparse: true,
+            special: SpecialPolicy::default(),
+        }
+    }
+}
+
+impl EntryPolicy {
+    /// Whether the special file `special` goes in the archive
+    pub(crate) fn stores(&self, special: Special) -> bool {
+        self.special == SpecialPolicy::Store && special != Special::Socket
+    }
+}
+
+/// What was done with one entry, by the [`EntryPolicy`] on compression or
+/// by the conflict and extraction safety policies of [`safety`](crate::safety)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Decision {
+    /// A symlink stored as a link
+    SymlinkStored,
+    /// A symlink archived as what it points to
+    SymlinkFollowed,
+    /// A second name of a file, stored as a hard link to the first one
+    HardlinkKept,
+    /// A second name of a file, stored with its contents again
+    HardlinkCopied,
+    /// A file stored without its holes
+    Sparse,
+    /// A FIFO or device node stored as such
+    SpecialStored,
+    /// A FIFO, socket or device node left out
+    SpecialSkipped,
+    /// An entry not unpacked, the file already there was kept
+    ExistingSkipped,
+    /// An entry unpacked under a free name beside the file already there
+    ExistingKeptBoth,
+    /// A tarbomb unpacked into the directory of its item name
+    TarbombRedirected,
+    /// An archive already there failing its item, reported by dry runs
+    ArchiveFails,
+    /// An archive already there left as it is, its item skipped
+    ArchiveSkipped,
+    /// An archive already there replaced
+    ArchiveOverwritten,
+    /// An archive already there kept, the new one written under a dated name
+    ArchiveVersioned,
+    /// An archive already there replaced by one with its entries and the item
+    ArchiveMerged,
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Decision::SymlinkStored => "symlink stored",
+            Decision::SymlinkFollowed => "symlink followed",
+            Decision::HardlinkKept => "hard link kept",
+            Decision::HardlinkCopied => "hard link copied",
+            Decision::Sparse => "stored sparse",
+            Decision::SpecialStored => "special file stored",
+            Decision::SpecialSkipped => "special file skipped",
+            Decision::ExistingSkipped => "skipped, the existing file is kept",
+            Decision::ExistingKeptBoth => "exists already, unpacked beside it",
+            Decision::TarbombRedirected => "tarbomb, unpacked into this directory",
+            Decision::ArchiveFails => "exists already, the item fails",
+            Decision::ArchiveSkipped => "exists already, the item is skipped",
+            Decision::ArchiveOverwritten => "exists already, overwritten",
+            Decision::ArchiveVersioned => "exists already, written beside it",
+            Decision::ArchiveMerged => "exists already, merged with the item",
+        })
+    }
+}
+
+/// One entry handled by a policy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EntryDecision {
+    /// Path of the entry in the archive, the directory a tarbomb went into,
+    /// or the archive already there
+    pub path: PathBuf,
+    pub decision: Decision,
+    /// Target of the symlink, first name of the hard link, or where an
+    /// entry or archive kept beside an existing one went
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<PathBuf>,
+}
+
+impl fmt::Display for EntryDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.decision)?;
+        match &self.link {
+            Some(link) => write!(f, " -> {}", link.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Kind of a special file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Special {
+    Fifo,
+    Socket,
+    CharDevice,
+    BlockDevice,
+}
+
+/// The kind of special file of `file_type`, `None` for files, directories and symlinks
+pub(crate) fn special_of(file_type: FileType) -> Option<Special> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        match file_type {
+            t if t.is_fifo() => Some(Special::Fifo),
+            t if t.is_socket() => Some(Special::Socket),
+            t if t.is_char_device() => Some(Special::CharDevice),
+            t if t.is_block_device() => Some(Special::BlockDevice),
+            _ => None,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = file_type;
+        None
+    }
+}
+
+/// Device and inode of a file with more than one name, `None` for any other
+pub(crate) fn link_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.is_file() && metadata.nlink() > 1).then(|| (metadata.dev(), metadata.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+/// Whether the file takes less room on disk than its size, having holes
+pub(crate) fn is_sparse(metadata: &Metadata) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.is_file() && metadata.blocks() * 512 < metadata.len()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        false
+    }
+}
+
+/// Header of the special file `metadata` describes
+pub(crate) fn special_header(metadata: &Metadata, special: Special) -> io::Result<tar::Header> {
+    let entry_type = match special {
+        Special::Fifo => tar::EntryType::Fifo,
+        Special::CharDevice => tar::EntryType::Char,
+        Special::BlockDevice => tar::EntryType::Block,
+        Special::Socket => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "sockets cannot be archived",
+            ));
+        }
+    };
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata(metadata);
+    header.set_entry_type(entry_type);
+    header.set_size(0);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let rdev = metadata.rdev();
+        header.set_device_major(libc::major(rdev as _) as u32)?;
+        header.set_device_minor(libc::minor(rdev as _) as u32)?;
+    }
+    Ok(header)
+}
+
+/// Create the FIFO or device node of `entry` at `dst`
+///
+/// `tar::Entry::unpack` writes them as empty regular files.
+pub(crate) fn make_special<R: io::Read>(entry: &tar::Entry<R>, dst: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            t if t.is_fifo() => libc::S_IFIFO,
+            t if t.is_character_special() => libc::S_IFCHR,
+            _ => libc::S_IFBLK,
+        };
+        let dev = match (header.device_major()?, header.device_minor()?) {
+            (Some(major), Some(minor)) => libc::makedev(major, minor),
+            _ => 0,
+        };
+        if dst.symlink_metadata().is_ok() {
+            std::fs::remove_file(dst)?;
+        }
+        let path = CString::new(dst.as_os_str().as_bytes())?;
+        let mode = kind | (header.mode()? & 0o7777) as libc::mode_t;
+        // SAFETY: path is a C string that outlives the call
+        match unsafe { libc::mknod(path.as_ptr(), mode, dev) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (entry, dst);
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "special files cannot be created here",
+        ))
+    }
+}
@@ -0,0 +1,225 @@
+use crate::archiver::{Action, BatchOptions};
+use crate::exec::{action_of, dir_size, human_size};
+use crate::frame;
+use crate::seekable::Index;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Room kept free beyond the estimate of a file system, for its metadata
+/// and the rounding of files to blocks: 5% of it
+fn margin(needed: u64) -> u64 {
+    needed / 20
+}
+
+/// Something found before a run that would make it stop halfway
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The file system holding `path` has `available` bytes free, the run
+    /// needs `needed`, the safety margin included
+    NoSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+    /// The run writes into or removes from `path`, and may not
+    NotWritable { path: PathBuf },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NoSpace {
+                path,
+                needed,
+                available,
+            } => write!(
+                f,
+                "not enough space for {path:?}: {} needed, {} free, {} short",
+                human_size(*needed),
+                human_size(*available),
+                human_size(needed - available)
+            ),
+            Problem::NotWritable { path } => write!(f, "{path:?} is not writable"),
+        }
+    }
+}
+
+/// What the checks of a run found
+#[derive(Debug, Default)]
+pub(crate) struct Findings {
+    /// What makes the run stop halfway
+    pub problems: Vec<Problem>,
+    /// Archives that only tell their unpacked size once decoded, left out
+    /// of the space check
+    pub not_sized: Vec<PathBuf>,
+}
+
+/// What a run writes to one file system
+#[derive(Default)]
+struct Volume {
+    /// First output directory found on it, or the nearest one that exists
+    path: PathBuf,
+    /// Bytes the run adds for good, outputs minus the inputs removed here
+    growth: u64,
+    /// Bytes each item holds at most while its input is not removed yet
+    held: Vec<u64>,
+}
+
+/// Check that the compress or extract run of `options` over `items` can
+/// finish, without changing anything
+///
+/// Each item is sized: an archive is taken to be as large as its item, and
+/// an archive unpacks to the sizes in its index, or else the size recorded
+/// at its end. The output of every file
+/// system must fit in its free space with a margin, counting the inputs
+/// removed there as they are and the items running side by side. Output
+/// directories, and the directories the inputs are removed from, must be
+/// writable. Items that cannot be sized are left to fail on their own,
+/// archives telling none of these sizes are listed in the findings.
+pub(crate) fn check(items: &[PathBuf], options: &BatchOptions) -> Findings {
+    let mut findings = Findings::default();
+    let mut volumes = BTreeMap::new();
+    let mut dirs = BTreeSet::new();
+    for f_path in items {
+        let action = action_of(f_path, options.mode);
+        if !matches!(action, Action::Compress | Action::Extract) {
+            continue;
+        }
+        let target = existing_ancestor(&options.item_target_dir(f_path));
+        dirs.insert(target.clone());
+        let removes = !options.preserve && options.members.is_empty();
+        if removes || options.flag && action == Action::Compress {
+            dirs.insert(existing_ancestor(f_path.parent().unwrap_or(Path::new("."))));
+        }
+        let (input, output) = match sizes(f_path, action) {
+            Ok((input, Some(output))) => (input, output),
+            Ok((_, None)) => {
+                findings.not_sized.push(f_path.clone());
+                continue;
+            }
+            Err(_) => continue,
+        };
+        let Some(device) = sys::device(&target) else {
+            continue;
+        };
+        let freed = match removes && sys::device(f_path) == Some(device) {
+            true => input,
+            false => 0,
+        };
+        let volume = volumes.entry(device).or_insert_with(|| Volume {
+            path: target.clone(),
+            ..Volume::default()
+        });
+        volume.growth += output.saturating_sub(freed);
+        volume.held.push(output.min(freed));
+    }
+
+    for volume in volumes.into_values() {
+        let mut held = volume.held;
+        held.sort_unstable_by(|a, b| b.cmp(a));
+        let estimate = volume.growth + held.iter().take(options.jobs.max(1)).sum::<u64>();
+        let needed = estimate + margin(estimate);
+        if let Some(available) = sys::available(&volume.path)
+            && available < needed
+        {
+            findings.problems.push(Problem::NoSpace {
+                path: volume.path,
+                needed,
+                available,
+            });
+        }
+    }
+    for dir in dirs {
+        if !sys::writable(&dir) {
+            findings.problems.push(Problem::NotWritable { path: dir });
+        }
+    }
+    findings
+}
+
+/// Bytes read from and written for the item at `f_path`, the latter `None`
+/// when it is not known without decoding
+fn sizes(f_path: &Path, action: Action) -> io::Result<(u64, Option<u64>)> {
+    let input = match f_path.is_dir() {
+        true => dir_size(f_path)?,
+        false => fs::metadata(f_path)?.len(),
+    };
+    let output = match action {
+        Action::Extract => unpacked_size(f_path)?,
+        _ => Some(input),
+    };
+    Ok((input, output))
+}
+
+/// Size of the files in the archive at `archive_path`, from its index or
+/// the size recorded at its end, the archive is never decoded
+fn unpacked_size(archive_path: &Path) -> io::Result<Option<u64>> {
+    match Index::read(archive_path)? {
+        Some(index) => Ok(Some(index.entries.iter().map(|e| e.size).sum())),
+        None => frame::read_unpacked_size(archive_path),
+    }
+}
+
+/// `path`, or the nearest directory above it that exists
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|dir| !dir.as_os_str().is_empty() && dir.exists())
+        .unwrap_or(Path::new("."))
+        .to_path_buf()
+}
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+    use std::path::Path;
+
+    /// File system of `path`
+    pub fn device(path: &Path) -> Option<u64> {
+        path.metadata().ok().map(|m| m.dev())
+    }
+
+    /// Bytes free for unprivileged users on the file system of `path`
+    pub fn available(path: &Path) -> Option<u64> {
+        let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+        // SAFETY: statvfs is plain C data, all zeros is valid
+        let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+        // SAFETY: path is a C string and stat a live statvfs, both outlive the call
+        match unsafe { libc::statvfs(path.as_ptr(), &mut stat) } {
+            0 => Some(stat.f_bavail as u64 * stat.f_frsize as u64),
+            _ => None,
+        }
+    }
+
+    /// Whether this process may create and remove entries in the directory `path`
+    pub fn writable(path: &Path) -> bool {
+        let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+            return false;
+        };
+        // SAFETY: path is a C string that outlives the call
+        unsafe { libc::access(path.as_ptr(), libc::W_OK | libc::X_OK) == 0 }
+    }
+}
+
+/// Free space is not looked up off Unix, only read-only directories are caught
+#[cfg(not(unix))]
+mod sys {
+    use std::path::Path;
+
+    pub fn device(_: &Path) -> Option<u64> {
+        Some(0)
+    }
+
+    pub fn available(_: &Path) -> Option<u64> {
+        None
+    }
+
+    pub fn writable(path: &Path) -> bool {
+        path.metadata()
+            .is_ok_and(|metadata| !metadata.permissions().readonly())
+    }
+}
@@ -0,0 +1,183 @@
+use crate::archiver::{Action, BatchSummary, ItemOutcome};
+use crate::error::Error;
+use crate::events::{Event, Reporter};
+use crate::list::ArchiveEntry;
+use crate::policy::EntryDecision;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Format of what a run prints on stdout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Progress lines for people
+    #[default]
+    Human,
+    /// One JSON record per item, then a summary record
+    Json,
+}
+
+/// Record of one item, one JSON line each
+#[derive(Serialize)]
+struct ItemRecord {
+    record: &'static str,
+    action: Action,
+    source: String,
+    destination: Option<String>,
+    original_bytes: Option<u64>,
+    compressed_bytes: Option<u64>,
+    ratio: Option<f64>,
+    duration_secs: f64,
+    status: &'static str,
+    error: Option<String>,
+    /// Entries of a listed archive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entries: Option<Vec<ArchiveEntry>>,
+    /// Symlinks, hard links, sparse and special files of a compressed item
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    decisions: Vec<EntryDecision>,
+}
+
+/// Record closing a run
+#[derive(Serialize)]
+struct SummaryRecord {
+    record: &'static str,
+    total: usize,
+    succeeded: usize,
+    skipped: usize,
+    failed: usize,
+    original_bytes: u64,
+    compressed_bytes: u64,
+    ratio: Option<f64>,
+    duration_secs: f64,
+}
+
+/// Machine-readable run report in JSON Lines
+///
+/// Each item is written as soon as it is done, with `"record": "item"`,
+/// followed by one `"record": "summary"` line at the end of the run.
+#[derive(Default)]
+pub struct Report {
+    sinks: Mutex<Vec<Box<dyn Write + Send>>>,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also write the records to `sink`
+    pub fn add_sink(&mut self, sink: Box<dyn Write + Send>) {
+        self.sinks.get_mut().unwrap().push(sink);
+    }
+
+    /// Record one processed item
+    fn item(&self, outcome: &ItemOutcome) {
+        self.write(&ItemRecord {
+            record: "item",
+            action: outcome.action,
+            source: outcome.source.to_string_lossy().into_owned(),
+            destination: outcome
+                .destination
+                .as_ref()
+                .map(|d| d.to_string_lossy().into_owned()),
+            original_bytes: outcome.original_bytes,
+            compressed_bytes: outcome.archive_bytes,
+            ratio: ratio(outcome.original_bytes, outcome.archive_bytes),
+            duration_secs: outcome.duration.as_secs_f64(),
+            status: "ok",
+            error: None,
+            entries: outcome.listing.clone(),
+            decisions: outcome.decisions.clone(),
+        });
+    }
+
+    /// Record one failed item
+    fn failed(&self, action: Action, f_path: &Path, error: &Error, duration: Duration) {
+        self.write(&ItemRecord {
+            record: "item",
+            action,
+            source: f_path.to_string_lossy().into_owned(),
+            destination: None,
+            original_bytes: None,
+            compressed_bytes: None,
+            ratio: None,
+            duration_secs: duration.as_secs_f64(),
+            status: "failed",
+            error: Some(error.to_string()),
+            entries: None,
+            decisions: vec![],
+        });
+    }
+
+    /// Record the totals of a run
+    fn summary(&self, summary: &BatchSummary, duration: Duration) {
+        let original_bytes = summary
+            .outcomes
+            .iter()
+            .filter_map(|o| o.original_bytes)
+            .sum();
+        let compressed_bytes = summary
+            .outcomes
+            .iter()
+            .filter_map(|o| o.archive_bytes)
+            .sum();
+        let skipped = summary
+            .outcomes
+            .iter()
+            .filter(|o| o.action == Action::Skip)
+            .count();
+        self.write(&SummaryRecord {
+            record: "summary",
+            total: summary.total(),
+            succeeded: summary.outcomes.len() - skipped,
+            skipped,
+            failed: summary.failed(),
+            original_bytes,
+            compressed_bytes,
+            ratio: ratio(Some(original_bytes), Some(compressed_bytes)),
+            duration_secs: duration.as_secs_f64(),
+        });
+    }
+
+    fn write<T: Serialize>(&self, record: &T) {
+        let Ok(line) = serde_json::to_string(record) else {
+            return;
+        };
+        for sink in self.sinks.lock().unwrap().iter_mut() {
+            if let Err(e) = writeln!(sink, "{line}").and_then(|_| sink.flush()) {
+                eprintln!("出错了! Failed to write report: {e}");
+            }
+        }
+    }
+}
+
+impl Reporter for Report {
+    fn event(&self, event: &Event<'_>) {
+        match *event {
+            Event::ItemDone { outcome, .. } => self.item(outcome),
+            Event::ItemFailed {
+                action,
+                path,
+                error,
+                duration,
+                ..
+            } => self.failed(action, path, error, duration),
+            Event::BatchDone { summary, duration } => self.summary(summary, duration),
+            _ => {}
+        }
+    }
+}
+
+/// Compressed size as a fraction of the original size
+fn ratio(original_bytes: Option<u64>, compressed_bytes: Option<u64>) -> Option<f64> {
+    match (original_bytes, compressed_bytes) {
+        (Some(original), Some(compressed)) if original > 0 => {
+            Some(compressed as f64 / original as f64)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,255 @@
+use crate::embed::Embedded;
+use crate::filter::Members;
+use crate::frame::open_decoder;
+use crate::seekable::Index;
+use chrono::{Local, NaiveDate};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// What extraction does with an entry whose destination already exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExistingPolicy {
+    /// Replace what is there
+    #[default]
+    Overwrite,
+    /// Leave what is there and skip the entry
+    Skip,
+    /// Unpack the entry under the first free name, e.g. `notes.1.txt`
+    KeepBoth,
+    /// Fail the item, before anything is unpacked when an index or an
+    /// embedded manifest lists the entries
+    Fail,
+}
+
+/// What extraction does with an archive whose entries are not all below `<name>/`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TarbombPolicy {
+    /// Unpack it into `<name>/` below the target instead
+    #[default]
+    Redirect,
+    /// Fail the item; without an index or embedded manifest it is judged by
+    /// its first entry
+    Refuse,
+}
+
+/// What compression does when the archive of an item exists already
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Fail the item, leaving the archive and the original as they are
+    #[default]
+    Fail,
+    /// Leave the archive and the original as they are and go on
+    Skip,
+    /// Replace the archive
+    Overwrite,
+    /// Write a new archive beside it, named with the date, e.g. `data.2026-10-18.tar.zst`
+    Versioned,
+    /// Replace the archive with one holding the item and the entries of the
+    /// old one the item has no entry for
+    Merge,
+}
+
+/// Where the entries of an archive go, found before unpacking it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Layout {
+    /// Directory to unpack into
+    pub target: PathBuf,
+    /// Whether the archive is a tarbomb sent into `<name>/`
+    pub redirected: bool,
+    /// Name every unpacked entry must be below, when only the first entry
+    /// was looked at
+    pub top: Option<OsString>,
+}
+
+/// Check the entries of the archive `archive_path` of the item `name` before
+/// unpacking it into `target`
+///
+/// The entry names come from the index of a seekable archive or from its
+/// embedded manifest. Paths leaving the target fail, so does a tarbomb
+/// unless `tarbomb` redirects it, and with [`ExistingPolicy::Fail`] so does
+/// any entry whose destination exists as something else than a directory.
+/// Without either, only the first selected entry is decoded to tell a
+/// tarbomb, and the rest is checked while unpacking, against
+/// [`Layout::top`]. Entries cut by `--strip-components` are placed as
+/// asked, never redirected.
+pub(crate) fn inspect(
+    archive_path: &Path,
+    name: &str,
+    target: &Path,
+    members: &Members,
+    tarbomb: TarbombPolicy,
+    existing: ExistingPolicy,
+) -> io::Result<Layout> {
+    let (paths, listed) = match listed_paths(archive_path)? {
+        Some(paths) => (paths, true),
+        None => (
+            first_selected(archive_path, members)?.into_iter().collect(),
+            false,
+        ),
+    };
+    let paths: Vec<_> = paths.into_iter().filter(|p| members.matches(p)).collect();
+    for path in &paths {
+        check_path(path)?;
+    }
+
+    let mut layout = Layout {
+        target: target.to_path_buf(),
+        redirected: false,
+        top: None,
+    };
+    // A dated version of an archive still holds its item below the plain name
+    let tops = [name, unversioned(name)].map(OsStr::new);
+    let bomb = !members.strips()
+        && paths
+            .iter()
+            .any(|path| top_of(path).is_some_and(|c| !tops.contains(&c)));
+    if bomb {
+        if tarbomb == TarbombPolicy::Refuse {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("entries outside {name:?}, refused as a tarbomb"),
+            ));
+        }
+        layout.target = target.join(name);
+        layout.redirected = true;
+    } else if !listed && !members.strips() {
+        let first = paths.first().and_then(|path| top_of(path));
+        layout.top = Some(first.unwrap_or(OsStr::new(name)).to_os_string());
+    }
+
+    if existing == ExistingPolicy::Fail && listed {
+        let conflicts: Vec<_> = paths
+            .iter()
+            .filter_map(|path| members.strip(path))
+            .map(|path| layout.target.join(path))
+            .filter(|dst| fs::symlink_metadata(dst).is_ok_and(|m| !m.is_dir()))
+            .collect();
+        if let Some(first) = conflicts.first() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{first:?} exists already, {} entries in the way",
+                    conflicts.len()
+                ),
+            ));
+        }
+    }
+    Ok(layout)
+}
+
+/// Entry paths from the index of a seekable archive or its embedded manifest
+fn listed_paths(archive_path: &Path) -> io::Result<Option<Vec<PathBuf>>> {
+    if let Some(index) = Index::read(archive_path)? {
+        return Ok(Some(index.entries.into_iter().map(|e| e.path).collect()));
+    }
+    Ok(Embedded::read(archive_path)?
+        .filter(|embedded| !embedded.manifest.is_empty())
+        .map(|embedded| embedded.manifest.into_iter().map(|e| e.path).collect()))
+}
+
+/// Path of the first entry `members` selects, decoding no further
+fn first_selected(archive_path: &Path, members: &Members) -> io::Result<Option<PathBuf>> {
+    let mut archive = tar::Archive::new(open_decoder(archive_path)?);
+    for entry in archive.entries()? {
+        let path = entry?.path()?.into_owned();
+        if members.matches(&path) {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+fn top_of(path: &Path) -> Option<&OsStr> {
+    path.components()
+        .find(|c| *c != Component::CurDir)
+        .map(|c| c.as_os_str())
+}
+
+/// Fail for an entry path not below `top`, a tarbomb showing only after
+/// unpacking began
+pub(crate) fn check_top(path: &Path, top: &OsStr) -> io::Result<()> {
+    match top_of(path).is_none_or(|c| c == top) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("entry {path:?} is outside {top:?}, a tarbomb found while unpacking"),
+        )),
+    }
+}
+
+/// Fail for an entry path that is absolute or goes up with `..`
+pub(crate) fn check_path(path: &Path) -> io::Result<()> {
+    match path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("entry {path:?} leaves the target"),
+        )),
+    }
+}
+
+/// Fail if `dir`, about to receive an entry, is reached through a symlink
+/// leading out of `target`
+///
+/// Checked before `dir` is created: the part that does not exist yet is
+/// made below the nearest part that does. `target` must be canonical.
+pub(crate) fn check_inside(dir: &Path, target: &Path) -> io::Result<()> {
+    let existing = dir
+        .ancestors()
+        .find(|part| fs::symlink_metadata(part).is_ok())
+        .unwrap_or(dir);
+    match existing.canonicalize()?.starts_with(target) {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{dir:?} leads out of the target through a symlink"),
+        )),
+    }
+}
+
+/// First name of a dated version of the archive `archive` with nothing
+/// there: `data.2026-10-18.tar.zst`, `data.2026-10-18.1.tar.zst`, ...
+pub(crate) fn versioned_name(archive: &Path, extension: &str) -> PathBuf {
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let stem = name.strip_suffix(extension).unwrap_or(&name);
+    let date = Local::now().format("%Y-%m-%d");
+    (0..)
+        .map(|n| match n {
+            0 => archive.with_file_name(format!("{stem}.{date}{extension}")),
+            n => archive.with_file_name(format!("{stem}.{date}.{n}{extension}")),
+        })
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .unwrap()
+}
+
+/// `name` without the date [`versioned_name`] added to it
+fn unversioned(name: &str) -> &str {
+    let dated = match name.rsplit_once('.') {
+        Some((dated, n)) if n.parse::<u32>().is_ok() => dated,
+        _ => name,
+    };
+    match dated.rsplit_once('.') {
+        Some((stem, date)) if NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok() => stem,
+        _ => name,
+    }
+}
+
+/// First name like `dst` with nothing there: `notes.1.txt`, `notes.2.txt`, ...
+pub(crate) fn free_name(dst: &Path) -> PathBuf {
+    let stem = dst.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = dst.extension().map(|e| format!(".{}", e.to_string_lossy()));
+    (1..)
+        .map(|n| dst.with_file_name(format!("{stem}.{n}{}", extension.as_deref().unwrap_or(""))))
+        .find(|candidate| fs::symlink_metadata(candidate).is_err())
+        .unwrap()
+}
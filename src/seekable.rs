@@ -0,0 +1,265 @@
+use crate::auxiliary::{CountingWriter, read_full};
+use crate::error::{Error, Operation, Result};
+use crate::frame::open_decoder_at;
+use crate::params::ZstdParams;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Uncompressed size of each frame of a seekable archive without an explicit one
+pub static FRAME_SIZE_DEFAULT: usize = 8 * 1024 * 1024;
+
+/// Smallest frame size accepted, smaller frames cost more in headers and
+/// index entries than seeking saves
+pub static FRAME_SIZE_MIN: usize = 64 * 1024;
+
+/// Fail for a frame size below [`FRAME_SIZE_MIN`]
+pub fn check_frame_size(frame_size: Option<usize>) -> Result<()> {
+    match frame_size {
+        Some(frame_size) if frame_size < FRAME_SIZE_MIN => Err(Error::InvalidOption(format!(
+            "frame size {frame_size} is below the minimum of {FRAME_SIZE_MIN} bytes"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Magic of the skippable frame holding the index, zstd decoders pass over it
+const INDEX_MAGIC: u32 = 0x184D_2A5E;
+/// Last bytes of a seekable archive, after the length of the index frame
+const INDEX_TAG: &[u8; 4] = b"zsti";
+/// Length of the index frame and the tag
+const FOOTER_SIZE: u64 = 8;
+
+/// Place of one independent zstd frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameInfo {
+    /// Where the frame starts in the archive file
+    pub compressed_offset: u64,
+    pub compressed_size: u64,
+    /// Where the content of the frame starts in the tar stream
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Place of one tar entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EntryInfo {
+    pub path: PathBuf,
+    /// Where the header of the entry starts in the tar stream
+    pub offset: u64,
+    /// Size of the file contents, 0 for anything else
+    pub size: u64,
+}
+
+/// Entry index of a seekable archive
+///
+/// It is kept in a skippable frame at the end of the archive, so the archive
+/// stays a plain `.tar.zst` to any zstd decoder. The frame ends with its own
+/// length and a tag, which is how it is found from the end of the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Index {
+    pub frames: Vec<FrameInfo>,
+    pub entries: Vec<EntryInfo>,
+}
+
+impl Index {
+    /// Read the index of `archive_path`, `None` for an archive without one
+    pub fn read(archive_path: &Path) -> io::Result<Option<Self>> {
+        let mut file = File::open(archive_path)?;
+        let len = file.metadata()?.len();
+        if len < FOOTER_SIZE {
+            return Ok(None);
+        }
+        file.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        let mut footer = [0; FOOTER_SIZE as usize];
+        file.read_exact(&mut footer)?;
+        if &footer[4..] != INDEX_TAG {
+            return Ok(None);
+        }
+        let frame_len = u64::from(u32::from_le_bytes(footer[..4].try_into().unwrap()));
+        if frame_len < 8 + FOOTER_SIZE || frame_len > len {
+            return Err(invalid("index frame length out of the archive"));
+        }
+
+        file.seek(SeekFrom::Start(len - frame_len))?;
+        let mut frame = vec![0; frame_len as usize];
+        file.read_exact(&mut frame)?;
+        if frame[..4] != INDEX_MAGIC.to_le_bytes() {
+            return Err(invalid("index frame has a wrong magic"));
+        }
+        let payload = &frame[8..frame.len() - FOOTER_SIZE as usize];
+        serde_json::from_slice(payload)
+            .map(Some)
+            .map_err(|e| invalid(&format!("index is unreadable: {e}")))
+    }
+
+    /// Append the index as a skippable frame to `writer`
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let payload = serde_json::to_vec(self).map_err(io::Error::other)?;
+        let frame_len = u32::try_from(payload.len() as u64 + 8 + FOOTER_SIZE)
+            .map_err(|_| invalid("index too large for a skippable frame"))?;
+        writer.write_all(&INDEX_MAGIC.to_le_bytes())?;
+        writer.write_all(&(frame_len - 8).to_le_bytes())?;
+        writer.write_all(&payload)?;
+        writer.write_all(&frame_len.to_le_bytes())?;
+        writer.write_all(INDEX_TAG)
+    }
+
+    /// Frame holding the tar stream at `offset`
+    pub fn frame_at(&self, offset: u64) -> Option<&FrameInfo> {
+        let i = self.frames.partition_point(|f| f.offset + f.size <= offset);
+        self.frames.get(i).filter(|f| f.offset <= offset)
+    }
+
+    /// Check that the frames follow each other and the entries lie in them
+    pub fn check(&self) -> io::Result<()> {
+        // Embedded data may come before the first frame
+        let mut compressed_offset = self.frames.first().map_or(0, |f| f.compressed_offset);
+        let mut offset = 0;
+        for frame in &self.frames {
+            if frame.compressed_offset != compressed_offset || frame.offset != offset {
+                return Err(invalid("index frames are not contiguous"));
+            }
+            compressed_offset += frame.compressed_size;
+            offset += frame.size;
+        }
+        match self.entries.iter().find(|e| e.offset >= offset) {
+            Some(entry) => Err(invalid(&format!(
+                "index entry {:?} lies past the last frame",
+                entry.path
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Compress everything `reader` yields into `writer` as independent frames
+/// of `frame_size` uncompressed bytes, placed by what `writer` counted before
+pub(crate) fn compress_frames<R: Read, W: Write>(
+    reader: &mut R,
+    mut writer: CountingWriter<W>,
+    frame_size: usize,
+    params: &ZstdParams,
+    zstd_workers: u32,
+    dictionary: &[u8],
+) -> io::Result<(W, Vec<FrameInfo>)> {
+    let mut frames: Vec<FrameInfo> = vec![];
+    let mut buf = vec![0; frame_size];
+    loop {
+        let n = read_full(reader, &mut buf)?;
+        if n == 0 && !frames.is_empty() {
+            break;
+        }
+        let compressed_offset = writer.count();
+        let mut encoder =
+            zstd::stream::Encoder::with_dictionary(&mut writer, params.level, dictionary)?;
+        params.apply(&mut encoder)?;
+        encoder.multithread(zstd_workers)?;
+        encoder.set_pledged_src_size(Some(n as u64))?;
+        encoder.write_all(&buf[..n])?;
+        encoder.finish()?;
+        frames.push(FrameInfo {
+            compressed_offset,
+            compressed_size: writer.count() - compressed_offset,
+            offset: frames.last().map_or(0, |f| f.offset + f.size),
+            size: n as u64,
+        });
+        if n < buf.len() {
+            break;
+        }
+    }
+    Ok((writer.into_inner(), frames))
+}
+
+/// Decode the frame `frame` of `archive_path` on its own
+pub fn decode_frame(archive_path: &Path, frame: &FrameInfo) -> io::Result<Vec<u8>> {
+    let decoder = open_decoder_at(archive_path, frame.compressed_offset)?;
+    let mut content = Vec::with_capacity(frame.size as usize);
+    decoder.take(frame.size).read_to_end(&mut content)?;
+    if content.len() as u64 != frame.size {
+        return Err(invalid("frame is shorter than the index says"));
+    }
+    Ok(content)
+}
+
+/// Decode the frames of `index` with `workers` threads, writing the tar
+/// stream to `writer` in order
+pub fn decode_frames<W: Write>(
+    archive_path: &Path,
+    index: &Index,
+    workers: usize,
+    writer: &mut W,
+) -> io::Result<()> {
+    for batch in index.frames.chunks(workers.max(1)) {
+        let decoded = thread::scope(|scope| {
+            let handles = batch
+                .iter()
+                .map(|frame| scope.spawn(move || decode_frame(archive_path, frame)))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join()
+                        .unwrap_or_else(|_| Err(io::Error::other("decoder thread panicked")))
+                })
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+        for content in decoded {
+            writer.write_all(&content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Unpack the entry `name` of `archive_path` into `target`
+///
+/// With an index, decoding starts at the frame holding the entry. Without one,
+/// the archive is read from the start until the entry turns up.
+/// Returns the size of the unpacked file contents.
+pub fn extract_entry(archive_path: &Path, name: &Path, target: &Path) -> Result<u64> {
+    let err = |e| Error::io(Operation::Extract, archive_path, e);
+    let index = Index::read(archive_path).map_err(err)?;
+    let (start, skip) = match &index {
+        Some(index) => {
+            let entry = index
+                .entries
+                .iter()
+                .find(|e| e.path == name)
+                .ok_or_else(|| err(not_found(name)))?;
+            let frame = index
+                .frame_at(entry.offset)
+                .ok_or_else(|| err(invalid("entry lies past the last frame")))?;
+            (frame.compressed_offset, entry.offset - frame.offset)
+        }
+        None => (0, 0),
+    };
+
+    let mut decoder = open_decoder_at(archive_path, start).map_err(err)?;
+    io::copy(&mut (&mut decoder).take(skip), &mut io::sink()).map_err(err)?;
+    let mut archive = tar::Archive::new(decoder);
+    for entry in archive.entries().map_err(err)? {
+        let mut entry = entry.map_err(err)?;
+        if entry.path().map_err(err)? == name {
+            std::fs::create_dir_all(target).map_err(err)?;
+            entry.unpack_in(target).map_err(err)?;
+            return Ok(entry.size());
+        }
+        if index.is_some() {
+            break;
+        }
+    }
+    Err(err(not_found(name)))
+}
+
+fn not_found(name: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("no entry {name:?} in the archive"),
+    )
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
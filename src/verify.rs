@@ -0,0 +1,174 @@
+use crate::auxiliary::read_full;
+use crate::error::{Error, Operation, Result};
+use crate::frame::open_decoder;
+use crate::policy;
+use crate::seekable::Index;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Read back a `.tar.zst` archive and check it
+///
+/// The whole zstd stream is decoded and every tar entry is read to its end.
+/// With `source`, the item the archive was made from, the entry names, file
+/// sizes and file contents are also compared against it, byte by byte, and
+/// symlink and hard link entries against the links they were made from.
+/// Special files missing from the archive were skipped on purpose and pass.
+/// The entry index of a seekable archive must match the entries read.
+/// Returns the number of entries in the archive.
+pub fn verify_archive(archive_path: &Path, source: Option<&Path>) -> Result<usize> {
+    verify_with(archive_path, source, &HashSet::new())
+}
+
+/// Like [`verify_archive`] against `source`, for an archive `source` was
+/// merged into: entries named in `old_entries`, those of the archive before,
+/// are only read when `source` has nothing of that name
+pub(crate) fn verify_merged(
+    archive_path: &Path,
+    source: &Path,
+    old_entries: &HashSet<PathBuf>,
+) -> Result<usize> {
+    verify_with(archive_path, Some(source), old_entries)
+}
+
+fn verify_with(
+    archive_path: &Path,
+    source: Option<&Path>,
+    old_entries: &HashSet<PathBuf>,
+) -> Result<usize> {
+    let err = |e| Error::io(Operation::Verify, archive_path, e);
+    let decoder = open_decoder(archive_path).map_err(err)?;
+    let mut archive = tar::Archive::new(decoder);
+
+    // Entries are named relative to the directory holding the source
+    let base = source.map(|source| source.parent().unwrap_or(Path::new("")));
+    // Each name, and whether it is a directory to look into
+    let mut names = HashMap::new();
+    for entry in archive.entries().map_err(err)? {
+        let mut entry = entry.map_err(err)?;
+        let name = entry.path().map_err(err)?.components().collect::<PathBuf>();
+        let carried = |base: &Path| {
+            old_entries.contains(&name) && fs::symlink_metadata(base.join(&name)).is_err()
+        };
+        match base {
+            Some(base) if !carried(base) => compare_entry(&mut entry, base, &name).map_err(err)?,
+            _ => {
+                io::copy(&mut entry, &mut io::sink()).map_err(err)?;
+            }
+        }
+        names.insert(name, entry.header().entry_type().is_dir());
+    }
+
+    if let Some(index) = Index::read(archive_path).map_err(err)? {
+        index.check().map_err(err)?;
+        if let Some(entry) = index.entries.iter().find(|e| !names.contains_key(&e.path)) {
+            return Err(err(mismatch(format!(
+                "{:?} is in the index but not in the archive",
+                entry.path
+            ))));
+        }
+    }
+
+    // Everything in the source must have made it into the archive
+    if let (Some(source), Some(base)) = (source, base) {
+        let mut missing = None;
+        walk_source(source, &mut |path, metadata| {
+            let name = path.strip_prefix(base).unwrap_or(path);
+            match names.get(name) {
+                Some(is_dir) => *is_dir,
+                None => {
+                    let skipped = policy::special_of(metadata.file_type()).is_some();
+                    if missing.is_none() && !skipped {
+                        missing = Some(name.to_path_buf());
+                    }
+                    false
+                }
+            }
+        })
+        .map_err(err)?;
+        if let Some(name) = missing {
+            return Err(err(mismatch(format!(
+                "{name:?} is missing from the archive"
+            ))));
+        }
+    }
+
+    Ok(names.len())
+}
+
+/// Compare one tar entry named `name` against its source below `base`
+fn compare_entry<R: Read>(entry: &mut tar::Entry<R>, base: &Path, name: &Path) -> io::Result<()> {
+    let source = &base.join(name);
+    let header_type = entry.header().entry_type();
+    let link = entry.link_name()?.map(|link| link.into_owned());
+    if header_type.is_symlink() {
+        if fs::read_link(source).ok() != link {
+            return Err(mismatch(format!("{source:?} is not the same symlink")));
+        }
+        return Ok(());
+    }
+    let metadata = fs::metadata(source).map_err(|e| {
+        mismatch(format!(
+            "{source:?} is in the archive but not readable: {e}"
+        ))
+    })?;
+    if header_type.is_hard_link() {
+        let first = base.join(link.unwrap_or_default());
+        if policy::link_id(&metadata) != fs::metadata(first).ok().as_ref().and_then(policy::link_id)
+        {
+            return Err(mismatch(format!("{source:?} is not the same hard link")));
+        }
+        return Ok(());
+    }
+    if header_type.is_fifo() || header_type.is_character_special() || header_type.is_block_special()
+    {
+        if policy::special_of(metadata.file_type()).is_none() {
+            return Err(mismatch(format!("{source:?} is not a special file")));
+        }
+        return Ok(());
+    }
+    if header_type.is_dir() {
+        if !metadata.is_dir() {
+            return Err(mismatch(format!("{source:?} is not a directory")));
+        }
+        return Ok(());
+    }
+    if metadata.len() != entry.size() {
+        return Err(mismatch(format!(
+            "size of {source:?} differs: {} in archive, {} on disk",
+            entry.size(),
+            metadata.len()
+        )));
+    }
+
+    // Stream both sides and compare chunk by chunk
+    let mut file = File::open(source)?;
+    let mut buf_archive = vec![0; 64 * 1024];
+    let mut buf_source = vec![0; 64 * 1024];
+    loop {
+        let n = read_full(entry, &mut buf_archive)?;
+        let m = read_full(&mut file, &mut buf_source[..n.max(1)])?;
+        if n != m || buf_archive[..n] != buf_source[..m] {
+            return Err(mismatch(format!("content of {source:?} differs")));
+        }
+        if n == 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Call `f` on `path` and everything below it, going into the directories
+/// `f` returns true for
+fn walk_source(path: &Path, f: &mut dyn FnMut(&Path, &Metadata) -> bool) -> io::Result<()> {
+    if f(path, &fs::symlink_metadata(path)?) {
+        for entry in fs::read_dir(path)? {
+            walk_source(&entry?.path(), f)?;
+        }
+    }
+    Ok(())
+}
+
+fn mismatch(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -1,8 +1,10 @@
 use assert_cmd::cargo;
 use predicates::prelude::*;
-use std::fs::{create_dir_all, metadata, remove_dir_all, write};
-use std::path::PathBuf;
+use std::fs::{create_dir_all, metadata, remove_dir_all, remove_file, write};
+use std::path::{Path, PathBuf};
 use zst_compress::auxiliary::DirGuard;
+use zst_compress::embed::Embedded;
+use zst_compress::seekable::{Index, extract_entry};
 
 #[test]
 fn test_cli() {
@@ -39,6 +41,1589 @@ fn test_cli() {
     .unwrap();
 }
 
+#[test]
+fn test_extract_streams() {
+    // Absolute paths, test_cli changes the working directory
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_streams");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("data")).unwrap();
+    write(test_dir.join("data/notes.txt"), "streamed\n".repeat(1000)).unwrap();
+    write(test_dir.join("single.txt"), "streamed\n").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg(&test_dir).assert().success();
+
+    // Nothing is written beside the archives, and a file that has the name
+    // the intermediate .tar had is not touched
+    write(test_dir.join("data.tar"), "not the archive").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("-x").arg(&test_dir).assert().success();
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("data/notes.txt")).unwrap(),
+        "streamed\n".repeat(1000)
+    );
+    assert!(test_dir.join("single.txt").is_file());
+    assert!(!test_dir.join("single.txt.tar").exists());
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("data.tar")).unwrap(),
+        "not the archive"
+    );
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_exit_codes() {
+    // A directory that cannot be entered keeps the old exit code 3
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("tests/data_does_not_exist")
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("change into directory"));
+
+    // A corrupt archive fails its item, and the batch with code 2
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_exit_codes");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    write(test_dir.join("broken.tar.zst"), "not zstd").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("-x")
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("1 of 1 items failed"));
+    assert!(test_dir.join("broken.tar.zst").is_file());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_jobs() {
+    run_test(
+        "tests/data_jobs",
+        &["--jobs", "2", "--threads", "2", "."],
+        "/2) Compress:",
+        &vec![false, false, false, true, true, true],
+        &["-x", "-j", "3", "."],
+        "/3) Extract:",
+        &vec![true, true, true, false, false, false],
+    )
+    .unwrap();
+
+    // One item far slower than the others, which finish first
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_jobs_lines");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    write(test_dir.join("big.bin"), noise(16 << 20)).unwrap();
+    for name in ["b", "c", "d", "e", "f"] {
+        write(test_dir.join(format!("{name}.txt")), name.repeat(100)).unwrap();
+    }
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    let output = cmd
+        .args(["-p", "-j", "2", "--threads", "2"])
+        .arg(&test_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Whole lines, counted as the items finish
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().filter(|l| l.contains("Compress:")).collect();
+    assert_eq!(lines.len(), 6);
+    for (i, line) in lines.iter().enumerate() {
+        assert!(line.starts_with(&format!("({}/6) Compress: \"./", i + 1)));
+        assert!(line.ends_with(".tar.zst\""));
+    }
+    assert!(lines[5].contains("big.bin"));
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_verify() {
+    // Absolute paths, other tests change the working directory
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_verify");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("dir")).unwrap();
+    write(test_dir.join("dir/text.txt"), "verify me\n".repeat(1000)).unwrap();
+    write(test_dir.join("single.txt"), "single file\n".repeat(1000)).unwrap();
+
+    // Compress with read back, originals are only removed once verified
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("--verify").arg(&test_dir).assert().success();
+    assert!(!test_dir.join("dir").exists());
+    assert!(test_dir.join("dir.tar.zst").is_file());
+
+    // Standalone check of the archives just written
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("--verify-only")
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("OK"));
+
+    // A truncated archive is reported and fails the run
+    let archive = test_dir.join("single.txt.tar.zst");
+    let data = std::fs::read(&archive).unwrap();
+    write(&archive, &data[..data.len() / 2]).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("--verify-only")
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("CORRUPT"));
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_interrupted() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_interrupted");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    write(test_dir.join("big.bin"), noise(8 << 20)).unwrap();
+
+    // Killed while it writes the archive
+    let mut child = std::process::Command::new(cargo::cargo_bin!(env!("CARGO_PKG_NAME")))
+        .args(["-z", "19", "--threads", "1"])
+        .arg(&test_dir)
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let partial = test_dir.join("zst_partial.big.bin.tar.zst");
+    let started = std::time::Instant::now();
+    while !partial.exists() {
+        assert!(
+            started.elapsed().as_secs() < 30,
+            "no temporary file written"
+        );
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    // Only the temporary file is left beside the original, never a cut archive
+    assert!(test_dir.join("big.bin").is_file());
+    assert!(!test_dir.join("big.bin.tar.zst").exists());
+
+    // The next run removes the temporary file and writes the archive
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-p", "--verify"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Remove stale temporary file: \"./zst_partial.big.bin.tar.zst\"",
+        ))
+        .stdout(predicate::str::contains("(1/1) Compress: \"./big.bin\""));
+    assert!(!partial.exists());
+    assert!(test_dir.join("big.bin.tar.zst").is_file());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_depth() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_depth");
+    let source = test_dir.join("projects");
+    let target = test_dir.join("archive");
+    let _ = remove_dir_all(&test_dir);
+    for run in ["p1/s1/r1", "p1/s1/r2", "p2/s1/r1"] {
+        create_dir_all(source.join(run)).unwrap();
+        write(source.join(run).join("data.txt"), run.repeat(1000)).unwrap();
+    }
+    create_dir_all(&target).unwrap();
+
+    // One archive per run, mirrored below the target
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--depth", "3", "-t"])
+        .arg(&target)
+        .arg(&source)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(3/3) Compress:"));
+    for run in ["p1/s1/r1", "p1/s1/r2", "p2/s1/r1"] {
+        assert!(target.join(format!("{run}.tar.zst")).is_file());
+        assert!(
+            target
+                .join(format!("{run}_archived-filelist.txt"))
+                .is_file()
+        );
+        assert!(!source.join(run).exists());
+    }
+    assert!(source.join("p1/s1").is_dir());
+
+    // Extraction finds the archives at any depth
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-x", "--depth", "2", "-t"])
+        .arg(&source)
+        .arg(&target)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(3/3) Extract:"));
+    for run in ["p1/s1/r1", "p1/s1/r2", "p2/s1/r1"] {
+        assert!(source.join(run).join("data.txt").is_file());
+        assert!(!target.join(format!("{run}.tar.zst")).exists());
+    }
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_report() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_report");
+    let report = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_report.jsonl");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("dir")).unwrap();
+    write(test_dir.join("dir/text.txt"), "report\n".repeat(1000)).unwrap();
+
+    // JSON on stdout replaces the progress lines, the report file gets the same records
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--format", "json", "--report"])
+        .arg(&report)
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            r#""record":"item","action":"compress""#,
+        ))
+        .stdout(predicate::str::contains("Compress:").not());
+    let records = std::fs::read_to_string(&report).unwrap();
+    let lines: Vec<_> = records.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].contains(r#""original_bytes":7000"#));
+    assert!(lines[0].contains(r#""status":"ok""#));
+    assert!(lines[1].contains(r#""record":"summary","total":1,"succeeded":1"#));
+
+    let _ = remove_dir_all(&test_dir);
+    let _ = std::fs::remove_file(&report);
+}
+
+#[test]
+fn test_verbosity() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_verbosity");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("dir/sub")).unwrap();
+    write(
+        test_dir.join("dir/sub/text.txt"),
+        "verbosity\n".repeat(1000),
+    )
+    .unwrap();
+
+    // Verbose lists the tar entries and the thread counts, the one item gets
+    // the whole budget whatever --jobs asks for
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-v", "-p", "-j", "2", "--threads", "2"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 at a time with 2 zstd threads"))
+        .stdout(predicate::str::contains("dir/sub/text.txt"))
+        .stdout(predicate::str::contains("Done: 1 items, 0 failed"));
+
+    // Quiet prints nothing without errors
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-q", "-x"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::is_empty());
+    assert!(test_dir.join("dir/sub/text.txt").is_file());
+
+    // Errors still get through
+    write(test_dir.join("bad.tar.zst"), "not an archive").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-q", "--verify-only"])
+        .arg(&test_dir)
+        .assert()
+        .failure()
+        .stdout(predicate::str::is_empty())
+        .stderr(predicate::str::contains("bad.tar.zst"));
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_filters() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_filters");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    for name in ["a.txt", "b.log", "c.tmp", "d.bin", "e.txt"] {
+        write(test_dir.join(name), name).unwrap();
+    }
+    let list = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_filters.txt");
+    write(&list, "# scratch files\n*.tmp\n\ne.*\n").unwrap();
+
+    // Repeated, comma-separated and listed patterns all apply
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-n", "-e", "*.log,*.bin", "--exclude-from"])
+        .arg(&list)
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(1/1) Compress:"))
+        .stdout(predicate::str::contains("a.txt"));
+
+    // A pattern that does not compile stops the run before any item
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-i", "*.txt", "-i", "[abc"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stdout(predicate::str::contains("Compress:").not())
+        .stderr(predicate::str::contains(r#"invalid pattern "[abc""#));
+    assert!(test_dir.join("a.txt").is_file());
+
+    let _ = remove_dir_all(&test_dir);
+    let _ = std::fs::remove_file(&list);
+}
+
+#[test]
+fn test_resume() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_resume");
+    let _ = remove_dir_all(&test_dir);
+    for name in ["a", "b", "c", "d"] {
+        create_dir_all(test_dir.join(name)).unwrap();
+        write(test_dir.join(name).join("data.txt"), name.repeat(5000)).unwrap();
+    }
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-p", "-i", "a,b,d"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    assert!(test_dir.join("zst_journal.jsonl").is_file());
+
+    // A run that died: a is archived, b left its temporary file, d is done
+    // and c was never reached
+    let archive = test_dir.join("b.tar.zst");
+    let data = std::fs::read(&archive).unwrap();
+    remove_file(&archive).unwrap();
+    write(
+        test_dir.join("zst_partial.b.tar.zst"),
+        &data[..data.len() / 2],
+    )
+    .unwrap();
+    let run = serde_json::json!({"record": "run", "mode": "compress", "source": test_dir});
+    write(
+        test_dir.join("zst_journal.jsonl"),
+        [
+            &run.to_string(),
+            r#"{"record":"item","item":"./a","state":"archived"}"#,
+            r#"{"record":"item","item":"./b","state":"started"}"#,
+            r#"{"record":"item","item":"./d","state":"done"}"#,
+            r#"{"record":"item","item":"./c","sta"#,
+        ]
+        .join("\n"),
+    )
+    .unwrap();
+
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--resume", "--verify"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3 of 9 items were started before"))
+        .stdout(predicate::str::contains(r#"Skip: "./d""#));
+    for name in ["a", "b", "c"] {
+        assert!(!test_dir.join(name).exists());
+    }
+    assert!(test_dir.join("d").is_dir());
+    assert!(!test_dir.join("zst_partial.b.tar.zst").exists());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("--verify-only").arg(&test_dir).assert().success();
+
+    // The journal must match the direction of the run
+    let journal = std::fs::read(test_dir.join("zst_journal.jsonl")).unwrap();
+    write(test_dir.join("zst_journal-extract.jsonl"), &journal).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-x", "--resume"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("read journal"));
+
+    // Extraction keeps its own journal, gone once every item succeeded,
+    // and leaves that of the compress run to restore
+    remove_file(test_dir.join("zst_journal-extract.jsonl")).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-x", "-p", "--resume"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    assert!(test_dir.join("a/data.txt").is_file());
+    assert!(!test_dir.join("zst_journal-extract.jsonl").exists());
+    assert_eq!(
+        std::fs::read(test_dir.join("zst_journal.jsonl")).unwrap(),
+        journal
+    );
+
+    // Only what the broken extract run began is unpacked over, items it
+    // never reached keep the existing policy
+    let run = serde_json::json!({"record": "run", "mode": "extract", "source": test_dir});
+    let started = r#"{"record":"item","item":"./b.tar.zst","state":"started"}"#;
+    write(
+        test_dir.join("zst_journal-extract.jsonl"),
+        format!("{run}\n{started}\n"),
+    )
+    .unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-p", "--fail-existing", "--resume", "-i", "a*"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("exists"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-p", "--fail-existing", "--resume", "-i", "b*"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    assert!(!test_dir.join("zst_journal-extract.jsonl").exists());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_dictionary() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_dictionary");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    for i in 0..200 {
+        let values: Vec<_> = (0..100).map(|j| (i * 7 + j * 13) % 1000).collect();
+        let record = format!(
+            r#"{{"sensor":"probe-{}","unit":"celsius","values":{values:?}}}"#,
+            i % 10
+        );
+        write(test_dir.join(format!("s{i}.json")), record).unwrap();
+    }
+
+    // Every archive is made with the trained dictionary kept beside them
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--train-dict", "--dict-size", "8192"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Compress with dictionary"));
+    let dict = std::fs::read_dir(&test_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.to_string_lossy().ends_with(".dict"))
+        .unwrap();
+
+    // Without the dictionary the archives cannot be read
+    let saved = std::fs::read(&dict).unwrap();
+    std::fs::remove_file(&dict).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("--verify-only")
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("dictionary zst_dict-"));
+
+    // Extraction finds it by the ID in the archives
+    write(&dict, saved).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("-x").arg(&test_dir).assert().success();
+    assert!(test_dir.join("s199.json").is_file());
+    assert!(!test_dir.join("s199.json.tar.zst").exists());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_list() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_list");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("photos/2024/summer")).unwrap();
+    write(test_dir.join("photos/2024/summer/beach.jpg"), vec![7; 3000]).unwrap();
+    write(test_dir.join("photos/index.txt"), "beach\n").unwrap();
+    write(test_dir.join("single.txt"), "alone\n").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg(&test_dir).assert().success();
+
+    // Every level shows up, whatever --leveldir the filelist was cut at
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--list", "-j", "2"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "List: \"./photos.tar.zst\" -> 5 entries",
+        ))
+        .stdout(predicate::str::contains("│  └──┬ summer"))
+        .stdout(predicate::str::contains("2.9KB │  │  └─── beach.jpg"))
+        .stdout(predicate::str::contains("─ single.txt"));
+
+    // A single archive, flat
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--list", "--list-style", "flat"])
+        .arg(test_dir.join("photos.tar.zst"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(1/1) List"))
+        .stdout(predicate::str::contains("drwx"))
+        .stdout(
+            predicate::str::is_match(r"-rw.{7}\s+3000 \S+ \S+ photos/2024/summer/beach.jpg")
+                .unwrap(),
+        );
+
+    // JSON has the entries in the item record
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    let output = cmd
+        .args(["--list", "--format", "json"])
+        .arg(test_dir.join("single.txt.tar.zst"))
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let item: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    assert_eq!(item["action"], "list");
+    assert_eq!(item["entries"][0]["path"], "single.txt");
+    assert_eq!(item["entries"][0]["type"], "file");
+    assert_eq!(item["entries"][0]["size"], 6);
+
+    // Nothing was extracted
+    assert!(!test_dir.join("photos").exists());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_seekable() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_seekable");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("logs/old")).unwrap();
+    for i in 0..50 {
+        let lines: String = (0..2000).map(|j| format!("{i} line {j}\n")).collect();
+        write(test_dir.join(format!("logs/old/day{i:02}.log")), lines).unwrap();
+    }
+    write(test_dir.join("notes.txt"), "not seekable\n").unwrap();
+
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--seekable", "--frame-size", "65536", "--verify", "-p"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let archive = test_dir.join("logs.tar.zst");
+    let index = Index::read(&archive).unwrap().unwrap();
+    assert!(index.frames.len() > 10);
+    assert_eq!(index.entries.len(), 52);
+
+    // One entry near the end is found without unpacking the rest
+    let out = test_dir.join("out");
+    let name = Path::new("logs/old/day47.log");
+    let bytes = extract_entry(&archive, name, &out).unwrap();
+    assert_eq!(bytes, metadata(test_dir.join(name)).unwrap().len());
+    assert_eq!(
+        std::fs::read(out.join(name)).unwrap(),
+        std::fs::read(test_dir.join(name)).unwrap()
+    );
+    assert!(!out.join("logs/old/day46.log").exists());
+    assert!(extract_entry(&archive, Path::new("logs/none"), &out).is_err());
+
+    // An archive without an index is searched from the start
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--include", "notes.txt", "-p", "--conflict", "overwrite"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let plain = test_dir.join("notes.txt.tar.zst");
+    assert!(Index::read(&plain).unwrap().is_none());
+    extract_entry(&plain, Path::new("notes.txt"), &out).unwrap();
+    assert!(out.join("notes.txt").is_file());
+
+    // Frames are decoded in parallel on extraction
+    remove_dir_all(test_dir.join("logs")).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-x", "--threads", "4", "--include", "logs*"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read(test_dir.join(name)).unwrap(),
+        std::fs::read(out.join(name)).unwrap()
+    );
+    assert_eq!(
+        std::fs::read_dir(test_dir.join("logs/old"))
+            .unwrap()
+            .count(),
+        50
+    );
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_members() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_members");
+    let _ = remove_dir_all(&test_dir);
+    for dir in ["plain", "seekable"] {
+        create_dir_all(test_dir.join(dir).join("app/etc/conf.d")).unwrap();
+        write(test_dir.join(dir).join("app/etc/main.conf"), "main\n").unwrap();
+        write(
+            test_dir.join(dir).join("app/etc/conf.d/extra.conf"),
+            "extra\n",
+        )
+        .unwrap();
+        write(test_dir.join(dir).join("app/data.bin"), vec![1; 200_000]).unwrap();
+        let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+        if dir == "seekable" {
+            cmd.args(["--seekable", "--frame-size", "65536"]);
+        }
+        cmd.arg(test_dir.join(dir)).assert().success();
+    }
+
+    // Globs and directories, with and without an index to seek with
+    for dir in ["plain", "seekable"] {
+        let source = test_dir.join(dir);
+        let out = test_dir.join(format!("{dir}_out"));
+        let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+        cmd.args(["-x", "--member", "*.conf", "-t"])
+            .arg(&out)
+            .arg(&source)
+            .assert()
+            .success();
+        assert!(out.join("app/etc/main.conf").is_file());
+        assert!(out.join("app/etc/conf.d/extra.conf").is_file());
+        assert!(!out.join("app/data.bin").exists());
+        // Only part of it was extracted, the archive stays
+        assert!(source.join("app.tar.zst").is_file());
+
+        let stripped = test_dir.join(format!("{dir}_stripped"));
+        let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+        cmd.args([
+            "-x",
+            "--member",
+            "app/etc/conf.d",
+            "--strip-components",
+            "2",
+        ])
+        .arg("-t")
+        .arg(&stripped)
+        .arg(&source)
+        .assert()
+        .success();
+        assert_eq!(
+            std::fs::read_to_string(stripped.join("conf.d/extra.conf")).unwrap(),
+            "extra\n"
+        );
+        assert!(!stripped.join("main.conf").exists());
+
+        let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+        cmd.args(["-x", "--member", "app/missing"])
+            .arg(&source)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains(
+                "no entry matches [\"app/missing\"]",
+            ));
+    }
+
+    // Member patterns are checked before anything is extracted
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["-x", "--member", "app/[", "-t"])
+        .arg(test_dir.join("never"))
+        .arg(test_dir.join("plain"))
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid pattern"));
+    assert!(!test_dir.join("never").exists());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_commands() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_commands");
+    let source = test_dir.join("source");
+    let out = test_dir.join("out");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(source.join("reports/2025")).unwrap();
+    write(source.join("reports/2025/q1.csv"), "a,b\n1,2\n").unwrap();
+    write(source.join("notes.md"), "# notes\n").unwrap();
+
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-z", "9", "--seekable", "-t"])
+        .arg(&out)
+        .arg(&source)
+        .assert()
+        .success();
+    assert!(!source.join("notes.md").exists());
+
+    // Each command takes only its own options
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-z", "9"])
+        .arg(&out)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("unexpected argument '-z'"));
+
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["verify"]).arg(&out).assert().success();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["list", "--style", "flat"])
+        .arg(out.join("reports.tar.zst"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("reports/2025/q1.csv"));
+
+    // Info reads the header and the index only
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["info"])
+        .arg(out.join("reports.tar.zst"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("index of 3 entries"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    let output = cmd
+        .args(["info", "--format", "json"])
+        .arg(out.join("notes.md.tar.zst"))
+        .output()
+        .unwrap();
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["record"], "info");
+    assert_eq!(info["entries"], 1);
+    assert_eq!(info["content_bytes"], 2048);
+
+    // Restore puts the originals back where the journal says they came from
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["restore"]).arg(&out).assert().success();
+    assert_eq!(
+        std::fs::read_to_string(source.join("reports/2025/q1.csv")).unwrap(),
+        "a,b\n1,2\n"
+    );
+    assert!(source.join("notes.md").is_file());
+    assert!(!out.join("notes.md.tar.zst").exists());
+
+    // Only compress runs can be restored, the restore itself left no journal
+    assert!(!source.join("zst_journal-extract.jsonl").exists());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["restore"])
+        .arg(&source)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("failed to read journal"));
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_zstd_params() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_zstd_params");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    let block: Vec<u8> = (0..1 << 20)
+        .map(|i: u32| (i.wrapping_mul(2654435761) >> 13) as u8)
+        .collect();
+    write(
+        test_dir.join("image.bin"),
+        [&block[..], &block[..]].concat(),
+    )
+    .unwrap();
+
+    // Levels and windows libzstd does not take are refused before any item
+    for args in [
+        &["-z", "22"][..],
+        &["-z", "-200000"],
+        &["--window-log", "40"],
+        &["--seekable", "--frame-size", "1"],
+    ] {
+        let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+        cmd.args(args)
+            .arg(&test_dir)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("invalid option"));
+    }
+    assert!(test_dir.join("image.bin").is_file());
+
+    // A window above the decoder default is read back without extra options
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["--long", "30", "--strategy", "lazy2", "--verify"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let archive = metadata(test_dir.join("image.bin.tar.zst")).unwrap();
+    assert!(archive.len() < 3 << 19);
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("-x").arg(&test_dir).assert().success();
+    assert_eq!(metadata(test_dir.join("image.bin")).unwrap().len(), 2 << 20);
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_config() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_config");
+    let source = test_dir.join("source");
+    let xdg = test_dir.join("xdg");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(xdg.join("zst_compress")).unwrap();
+    create_dir_all(&source).unwrap();
+    write(source.join("notes.md"), "# notes\n").unwrap();
+    write(source.join("debug.log"), "trace\n").unwrap();
+    write(
+        xdg.join("zst_compress/config.toml"),
+        "exclude = [\"*.log\"]\n\n[profile.cold-storage]\nlevel = 19\n",
+    )
+    .unwrap();
+    write(
+        source.join("zst_compress.toml"),
+        "leveldir = 2\n\n[profile.cold-storage]\nseekable = true\npreserve = true\ntarget = \"../cold\"\n",
+    )
+    .unwrap();
+
+    // Levels past 19 from the environment are refused, the command line wins
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.env("XDG_CONFIG_HOME", &xdg)
+        .env("ZST_COMPRESS_LEVEL", "22")
+        .args(["compress", "--profile", "cold-storage"])
+        .arg(&source)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("invalid option"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.env("XDG_CONFIG_HOME", &xdg)
+        .env("ZST_COMPRESS_LEVEL", "22")
+        .env("ZST_COMPRESS_PROFILE", "cold-storage")
+        .args(["compress", "-z", "3"])
+        .arg(&source)
+        .assert()
+        .success();
+
+    // The profile brings the target, relative to its file, and the format
+    let cold = test_dir.join("cold");
+    assert!(source.join("notes.md").is_file());
+    assert!(!cold.join("debug.log.tar.zst").exists());
+    assert!(!cold.join("zst_compress.toml.tar.zst").exists());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["info"])
+        .arg(cold.join("notes.md.tar.zst"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("seekable"));
+
+    // A relative directory still takes the target from the file, and other
+    // variables sharing the prefix are left alone
+    write(source.join("zst_compress.toml"), "target = \"out\"\n").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.current_dir(&test_dir)
+        .env("XDG_CONFIG_HOME", &xdg)
+        .env("ZST_COMPRESS_CACHE_DIR", "/nowhere")
+        .args(["compress", "-p", "source"])
+        .assert()
+        .success();
+    assert!(source.join("out/notes.md.tar.zst").is_file());
+    assert!(!source.join("source").exists());
+
+    // Unknown profiles and keys are errors, not silently ignored
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.env("XDG_CONFIG_HOME", &xdg)
+        .args(["compress", "--profile", "hot"])
+        .arg(&source)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("no profile \"hot\""));
+    write(source.join("zst_compress.toml"), "levle = 3\n").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.env("XDG_CONFIG_HOME", &xdg)
+        .arg(&source)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("failed to read config file"));
+    assert!(source.join("notes.md").is_file());
+
+    // The command line turns off what a config file turns on
+    write(
+        source.join("zst_compress.toml"),
+        "preserve = true\nseekable = true\nembed = true\n",
+    )
+    .unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.env("XDG_CONFIG_HOME", &xdg)
+        .args(["compress", "--no-preserve", "--no-seekable", "--no-embed"])
+        .arg(&source)
+        .assert()
+        .success();
+    assert!(!source.join("notes.md").exists());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["info"])
+        .arg(source.join("notes.md.tar.zst"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("seekable").not())
+        .stdout(predicate::str::contains("Embedded").not());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_manifest() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_manifest");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("logs/2025/01/02")).unwrap();
+    write(test_dir.join("logs/2025/01/02/app.log"), "hello\n").unwrap();
+    write(test_dir.join("notes, draft.md"), "# notes\n").unwrap();
+
+    // The manifest goes below the listing depth and hashes every file
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-l", "0", "--manifest", "-t"])
+        .arg(&test_dir)
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let manifest = std::fs::read_to_string(test_dir.join("logs_archived-manifest.jsonl")).unwrap();
+    let entries: Vec<serde_json::Value> = manifest
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(entries.len(), 5);
+    let log = &entries[4];
+    assert_eq!(log["path"], "logs/2025/01/02/app.log");
+    assert_eq!(log["type"], "file");
+    assert_eq!(log["size"], 6);
+    assert_eq!(
+        log["sha256"],
+        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+    );
+    assert_eq!(entries[0]["type"], "dir");
+    assert_eq!(entries[0]["sha256"], serde_json::Value::Null);
+
+    // Manifests are never items, and go with their archive on extraction
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract"]).arg(&test_dir).assert().success();
+    assert!(!test_dir.join("logs_archived-manifest.jsonl").exists());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "--manifest=csv"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let manifest =
+        std::fs::read_to_string(test_dir.join("notes, draft.md_archived-manifest.csv")).unwrap();
+    let mut lines = manifest.lines();
+    assert_eq!(lines.next(), Some("path,type,size,mtime,mode,link,sha256"));
+    assert!(
+        lines
+            .next()
+            .unwrap()
+            .starts_with("\"notes, draft.md\",file,8,")
+    );
+    assert!(
+        !test_dir
+            .join("notes, draft.md_archived-manifest.csv.tar.zst")
+            .exists()
+    );
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_embed() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_embed");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("scans/2025")).unwrap();
+    write(test_dir.join("scans/2025/page1.txt"), "hello\n").unwrap();
+
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "--embed", "--meta", "no-equals-sign"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("expected KEY=VALUE"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "--embed", "--seekable", "--note", "tape 7"])
+        .args(["--meta", "project=alpha"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+
+    // Only the archive is needed to read back what was embedded
+    let archive = test_dir.join("scans.tar.zst");
+    std::fs::remove_file(test_dir.join("scans_archived-filelist.txt")).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    let output = cmd
+        .args(["info", "--format", "json"])
+        .arg(&archive)
+        .output()
+        .unwrap();
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let embedded = &info["embedded"];
+    assert_eq!(embedded["metadata"]["note"], "tape 7");
+    assert_eq!(embedded["metadata"]["project"], "alpha");
+    assert_eq!(embedded["metadata"]["level"], "5");
+    assert_eq!(embedded["manifest"].as_array().unwrap().len(), 3);
+    assert_eq!(info["entries"], 3);
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["info", "--listing"])
+        .arg(&archive)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Embedded:   metadata, listing"))
+        .stdout(predicate::str::contains("└─── page1.txt"));
+
+    // Decoders pass over the embedded frame, the index still points at the entries
+    let single = test_dir.join("single");
+    extract_entry(&archive, Path::new("scans/2025/page1.txt"), &single).unwrap();
+    assert!(single.join("scans/2025/page1.txt").is_file());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract"]).arg(&test_dir).assert().success();
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("scans/2025/page1.txt")).unwrap(),
+        "hello\n"
+    );
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_ownership() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_ownership");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("shared")).unwrap();
+    let file = test_dir.join("shared/data.txt");
+    write(&file, "hello\n").unwrap();
+    // SAFETY: geteuid has no preconditions
+    let root = unsafe { libc::geteuid() } == 0;
+    if root {
+        std::os::unix::fs::lchown(&file, Some(1234), Some(1234)).unwrap();
+    }
+    // After the chown, which clears the setgid bit
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o2755)).unwrap();
+    // File systems without user xattrs still run the rest
+    let xattrs = xattr::set(&file, "user.test", b"kept").is_ok();
+    // Links stored as links carry their own attributes, even dangling ones
+    std::os::unix::fs::symlink("data.txt", test_dir.join("shared/alias")).unwrap();
+    std::os::unix::fs::symlink("nowhere", test_dir.join("shared/gone")).unwrap();
+
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "--xattrs", "--owner-names"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "--xattrs", "--same-permissions"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let metadata = std::fs::metadata(&file).unwrap();
+    if root {
+        assert_eq!((metadata.uid(), metadata.gid()), (1234, 1234));
+    }
+    assert_eq!(metadata.mode() & 0o7777, 0o2755);
+    if xattrs {
+        assert_eq!(
+            xattr::get(&file, "user.test").unwrap().as_deref(),
+            Some(&b"kept"[..])
+        );
+        assert_eq!(
+            xattr::get(test_dir.join("shared/alias"), "user.test").unwrap(),
+            None
+        );
+    }
+    assert!(test_dir.join("shared/gone").is_symlink());
+
+    // With --no-same-owner, entries belong to whoever extracts them
+    remove_dir_all(test_dir.join("shared")).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--no-same-owner"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let metadata = std::fs::metadata(&file).unwrap();
+    // SAFETY: as above
+    assert_eq!(metadata.uid(), unsafe { libc::geteuid() });
+    if xattrs {
+        assert_eq!(xattr::get(&file, "user.test").unwrap(), None);
+    }
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_entry_policy() {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt, symlink};
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_policy");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("project/sub")).unwrap();
+    create_dir_all(test_dir.join("shared")).unwrap();
+    write(test_dir.join("shared/huge.bin"), "far away\n").unwrap();
+    write(test_dir.join("project/a.txt"), "twice\n").unwrap();
+    std::fs::hard_link(
+        test_dir.join("project/a.txt"),
+        test_dir.join("project/sub/b.txt"),
+    )
+    .unwrap();
+    symlink("../shared", test_dir.join("project/shared")).unwrap();
+    let fifo = std::ffi::CString::new(
+        test_dir
+            .join("project/pipe")
+            .into_os_string()
+            .into_encoded_bytes(),
+    )
+    .unwrap();
+    // SAFETY: fifo is a C string that outlives the call
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+    // By default links stay links and the FIFO is left out, each said in the record
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    let output = cmd
+        .args(["compress", "--preserve", "--verify", "--format", "json"])
+        .args(["--include", "project"])
+        .arg(&test_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let record: serde_json::Value =
+        serde_json::from_slice(output.stdout.split(|&b| b == b'\n').next().unwrap()).unwrap();
+    let decisions: Vec<_> = record["decisions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|d| (d["path"].as_str().unwrap(), d["decision"].as_str().unwrap()))
+        .collect();
+    assert_eq!(
+        decisions,
+        [
+            ("project/pipe", "special-skipped"),
+            ("project/shared", "symlink-stored"),
+            ("project/sub/b.txt", "hardlink-kept"),
+        ]
+    );
+    let out = test_dir.join("out");
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args([
+        "extract",
+        "--preserve",
+        "--include",
+        "project.tar.zst",
+        "-t",
+    ])
+    .arg(&out)
+    .arg(&test_dir)
+    .assert()
+    .success();
+    assert_eq!(
+        std::fs::read_link(out.join("project/shared")).unwrap(),
+        Path::new("../shared")
+    );
+    assert_eq!(
+        std::fs::metadata(out.join("project/sub/b.txt"))
+            .unwrap()
+            .nlink(),
+        2
+    );
+    assert!(!out.join("project/pipe").exists());
+
+    // Following the symlink brings in what it points to, the FIFO is recreated
+    remove_dir_all(&out).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args([
+        "compress",
+        "--preserve",
+        "--verify",
+        "--conflict",
+        "overwrite",
+        "--include",
+        "project",
+    ])
+    .args([
+        "--symlinks",
+        "follow",
+        "--hardlinks",
+        "copy",
+        "--special",
+        "store",
+    ])
+    .arg(&test_dir)
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(
+        "project/shared: symlink followed -> ../shared",
+    ))
+    .stdout(predicate::str::contains(
+        "project/sub/b.txt: hard link copied -> project/a.txt",
+    ))
+    .stdout(predicate::str::contains(
+        "project/pipe: special file stored",
+    ));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args([
+        "extract",
+        "--preserve",
+        "--include",
+        "project.tar.zst",
+        "-t",
+    ])
+    .arg(&out)
+    .arg(&test_dir)
+    .assert()
+    .success();
+    assert_eq!(
+        std::fs::read_to_string(out.join("project/shared/huge.bin")).unwrap(),
+        "far away\n"
+    );
+    assert_eq!(
+        std::fs::metadata(out.join("project/sub/b.txt"))
+            .unwrap()
+            .nlink(),
+        1
+    );
+    let pipe = std::fs::symlink_metadata(out.join("project/pipe")).unwrap();
+    assert!(pipe.file_type().is_fifo());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_extract_safety() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_safety");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    // Entries named raw, as `set_path` refuses `..`
+    let write_archive = |name: &str, entries: &[&str]| {
+        let file = std::fs::File::create(test_dir.join(name)).unwrap();
+        let mut builder = tar::Builder::new(zstd::Encoder::new(file, 3).unwrap());
+        for entry in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..entry.len()].copy_from_slice(entry.as_bytes());
+            header.set_size(6);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder.append(&header, &b"inside"[..]).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    };
+    write_archive("bomb.tar.zst", &["a.txt", "b.txt"]);
+    write_archive("evil.tar.zst", &["evil/ok.txt", "evil/../../escaped.txt"]);
+    write_archive("late.tar.zst", &["late/ok.txt", "stray.txt"]);
+
+    // Entries outside `bomb/` are refused, or sent into it
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args([
+        "extract",
+        "--preserve",
+        "--tarbomb",
+        "refuse",
+        "-i",
+        "bomb*",
+    ])
+    .arg(&test_dir)
+    .assert()
+    .code(2)
+    .stderr(predicate::str::contains("refused as a tarbomb"));
+    assert!(!test_dir.join("a.txt").exists());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "-i", "bomb*"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("bomb: tarbomb"));
+    assert!(test_dir.join("bomb/a.txt").is_file());
+    assert!(!test_dir.join("a.txt").exists());
+
+    // Without a list of entries, the first one decides and a later stray fails the item
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "-i", "late*"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("a tarbomb found while unpacking"));
+    assert!(!test_dir.join("stray.txt").exists());
+
+    // An entry leaving the target fails the item and is not written
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "-i", "evil*"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("leaves the target"));
+    assert!(!test_dir.join("../escaped.txt").exists());
+
+    // Directories below a symlink unpacked just before are not made outside
+    #[cfg(unix)]
+    {
+        let outside = test_dir.join("outside");
+        create_dir_all(&outside).unwrap();
+        let file = std::fs::File::create(test_dir.join("x.tar.zst")).unwrap();
+        let mut builder = tar::Builder::new(zstd::Encoder::new(file, 3).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder
+            .append_link(&mut header, "x/link", &outside)
+            .unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_size(6);
+        header.set_mode(0o644);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(0);
+        builder
+            .append_data(&mut header, "x/link/sub/deep/f", &b"inside"[..])
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+        let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+        cmd.args(["extract", "--preserve", "-i", "x.tar.zst", "-t"])
+            .arg(test_dir.join("out"))
+            .arg(&test_dir)
+            .assert()
+            .code(2)
+            .stderr(predicate::str::contains("leads out of the target"));
+        assert!(!outside.join("sub").exists());
+    }
+
+    // Files already there are overwritten, kept or kept beside the new ones
+    write(test_dir.join("bomb/a.txt"), "edited").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "--fail-existing", "-i", "bomb*"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("exists already"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "--skip-existing", "-i", "bomb*"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "a.txt: skipped, the existing file is kept",
+        ));
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("bomb/a.txt")).unwrap(),
+        "edited"
+    );
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "--keep-both", "-i", "bomb*"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "a.txt: exists already, unpacked beside it -> a.1.txt",
+        ));
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("bomb/a.1.txt")).unwrap(),
+        "inside"
+    );
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "--preserve", "--overwrite", "-i", "bomb*"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("bomb/a.txt")).unwrap(),
+        "inside"
+    );
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_compress_conflict() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_conflict");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("data")).unwrap();
+    write(test_dir.join("data/a.txt"), "one").unwrap();
+    write(test_dir.join("data/b.txt"), "two").unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-p", "--embed"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let archive = test_dir.join("data.tar.zst");
+    let before = std::fs::read(&archive).unwrap();
+    std::fs::remove_file(test_dir.join("data/b.txt")).unwrap();
+    write(test_dir.join("data/a.txt"), "new").unwrap();
+    write(test_dir.join("data/c.txt"), "three").unwrap();
+
+    // A dry run lists the conflict, a real run fails the item
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-n"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "data.tar.zst: exists already, the item fails",
+        ));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-p"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("the archive exists already"));
+
+    // A resumed run began on the item without writing the archive, it is not its own
+    let journal = test_dir.join("zst_journal.jsonl");
+    let mut lines = std::fs::read_to_string(&journal).unwrap();
+    lines.push_str("{\"record\":\"item\",\"item\":\"./data\",\"state\":\"started\"}\n");
+    write(&journal, lines).unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-p", "--resume"])
+        .arg(&test_dir)
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("the archive exists already"));
+    assert_eq!(std::fs::read(&archive).unwrap(), before);
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "--conflict", "skip"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("the item is skipped"));
+    assert_eq!(std::fs::read(&archive).unwrap(), before);
+    assert!(test_dir.join("data").is_dir());
+
+    // A dated version is written beside the archive
+    let versioned = format!("data.{}.tar.zst", chrono::Local::now().format("%Y-%m-%d"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-p", "--conflict", "versioned"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "data.tar.zst: exists already, written beside it -> {versioned}"
+        )));
+    assert!(test_dir.join(&versioned).is_file());
+    assert!(
+        test_dir
+            .join(versioned.replace(".tar.zst", "_archived-filelist.txt"))
+            .is_file()
+    );
+    assert_eq!(std::fs::read(&archive).unwrap(), before);
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-p", "-i", &versioned, "-t"])
+        .arg(test_dir.join("versioned"))
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tarbomb").not());
+    assert_eq!(
+        std::fs::read_to_string(test_dir.join("versioned/data/a.txt")).unwrap(),
+        "new"
+    );
+
+    // Merging keeps the entries the item no longer has
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "--conflict", "merge", "--verify", "--embed"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "exists already, merged with the item",
+        ));
+    let manifest: Vec<_> = Embedded::read(&archive)
+        .unwrap()
+        .unwrap()
+        .manifest
+        .into_iter()
+        .map(|e| e.path)
+        .collect();
+    for name in ["data/a.txt", "data/b.txt", "data/c.txt"] {
+        assert!(manifest.contains(&PathBuf::from(name)));
+    }
+    let out = test_dir.join("out");
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-p", "-i", "data.tar.zst", "-t"])
+        .arg(&out)
+        .arg(&test_dir)
+        .assert()
+        .success();
+    for (name, content) in [("a.txt", "new"), ("b.txt", "two"), ("c.txt", "three")] {
+        assert_eq!(
+            std::fs::read_to_string(out.join("data").join(name)).unwrap(),
+            content
+        );
+    }
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_preflight() {
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_preflight");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    // A sparse file larger than the free space, taking none of it
+    let path = std::ffi::CString::new(test_dir.to_str().unwrap()).unwrap();
+    // SAFETY: statvfs is plain C data, all zeros is valid
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: path is a C string and stat a live statvfs, both outlive the call
+    assert_eq!(unsafe { libc::statvfs(path.as_ptr(), &mut stat) }, 0);
+    let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+    let huge = std::fs::File::create(test_dir.join("huge.img")).unwrap();
+    huge.set_len(free * 2 + (1 << 30)).unwrap();
+    write(test_dir.join("small.txt"), "small").unwrap();
+
+    // A dry run prints the shortfall, a real run stops before anything
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-n"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("not enough space"))
+        .stderr(predicate::str::contains("short"));
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.arg("compress")
+        .arg(&test_dir)
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("nothing was changed"));
+    assert!(!test_dir.join("small.txt.tar.zst").exists());
+    assert!(!test_dir.join("zst_journal.jsonl").exists());
+    assert!(test_dir.join("small.txt").is_file());
+
+    // Items that fit pass, the check can be turned off
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-n", "-i", "small.txt"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-n", "--no-preflight"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stderr(predicate::str::is_empty());
+
+    // Archives are sized without decoding them, others are said to be left out
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["compress", "-p", "-i", "small.txt"])
+        .arg(&test_dir)
+        .assert()
+        .success();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-n"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("unknown without decoding").not());
+    let file = std::fs::File::create(test_dir.join("other.tar.zst")).unwrap();
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, 3).unwrap());
+    builder
+        .append_path_with_name(test_dir.join("small.txt"), "other/small.txt")
+        .unwrap();
+    builder.into_inner().unwrap().finish().unwrap();
+    let mut cmd = cargo::cargo_bin_cmd!(env!("CARGO_PKG_NAME"));
+    cmd.args(["extract", "-p", "-i", "other*"])
+        .arg(&test_dir)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "other.tar.zst\" unknown without decoding it",
+        ));
+    assert!(test_dir.join("other/small.txt").is_file());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
 /// Runs a complete test cycle with compression and extraction
 ///
 /// # Arguments
@@ -267,3 +1852,16 @@ fn run_test_command(
         .stdout(predicate::str::contains(expected_output));
     Ok(())
 }
+
+/// `len` bytes zstd cannot shrink, the same on every call
+fn noise(len: usize) -> Vec<u8> {
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
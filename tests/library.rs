@@ -0,0 +1,89 @@
+use std::fs::{create_dir_all, remove_dir_all, write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use zst_compress::archiver::{Action, Archiver, BatchOptions, Mode};
+
+/// Held during runs, which change the working directory of the test process
+static RUNS: Mutex<()> = Mutex::new(());
+
+#[test]
+fn test_library() {
+    let _run = RUNS.lock().unwrap();
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_library");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(test_dir.join("dir")).unwrap();
+    write(test_dir.join("dir/text.txt"), "library test\n".repeat(1000)).unwrap();
+    write(test_dir.join("note.tmp"), "skipped by the filter").unwrap();
+    write(test_dir.join("zst_partial.dir.tar.zst"), "left by a crash").unwrap();
+
+    // Compress the whole directory, keeping the originals
+    let options = BatchOptions::new(&test_dir)
+        .exclude("*.tmp")
+        .level(3)
+        .preserve(true);
+    let summary = Archiver::new(options).run().unwrap();
+    assert_eq!(summary.failed(), 0);
+    assert_eq!(summary.total(), 1);
+    let outcome = &summary.outcomes[0];
+    assert_eq!(outcome.action, Action::Compress);
+    assert!(test_dir.join("dir.tar.zst").is_file());
+    assert!(test_dir.join("dir").is_dir());
+    assert!(!test_dir.join("zst_partial.dir.tar.zst").exists());
+
+    // Extract one item through the per-item API
+    remove_dir_all(test_dir.join("dir")).unwrap();
+    let archiver = Archiver::new(BatchOptions::new(&test_dir).mode(Mode::Extract));
+    let outcome = archiver
+        .process_item(&test_dir.join("dir.tar.zst"), 1, 1)
+        .unwrap();
+    assert_eq!(outcome.action, Action::Extract);
+    assert_eq!(outcome.destination, Some(test_dir.join("dir")));
+    assert!(test_dir.join("dir/text.txt").is_file());
+    assert!(!test_dir.join("dir.tar.zst").exists());
+
+    let _ = remove_dir_all(&test_dir);
+}
+
+#[test]
+fn test_library_jobs() {
+    let _run = RUNS.lock().unwrap();
+    let test_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data_library_jobs");
+    let _ = remove_dir_all(&test_dir);
+    create_dir_all(&test_dir).unwrap();
+    // One item far slower than the others, which finish before it
+    let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+    let noise: Vec<u8> = (0..16 << 20)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    write(test_dir.join("big.bin"), noise).unwrap();
+    for name in ["b", "c", "d", "e"] {
+        write(test_dir.join(format!("{name}.txt")), name.repeat(100)).unwrap();
+    }
+
+    // Outcomes keep the order of the items, not the order they finished in
+    let options = BatchOptions::new(&test_dir)
+        .jobs(2)
+        .threads(2)
+        .preserve(true);
+    let archiver = Archiver::new(options);
+    let items = archiver.collect_items().unwrap();
+    let summary = archiver.run().unwrap();
+    assert_eq!(summary.failed(), 0);
+    let sources: Vec<_> = summary
+        .outcomes
+        .iter()
+        .map(|o| o.source.file_name().unwrap().to_owned())
+        .collect();
+    let expected: Vec<_> = items
+        .iter()
+        .map(|item| item.file_name().unwrap().to_owned())
+        .collect();
+    assert_eq!(sources, expected);
+
+    let _ = remove_dir_all(&test_dir);
+}